@@ -0,0 +1,102 @@
+//! Throughput of the per-byte hot paths: splitting client traffic into
+//! stanzas and filtering presence. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use where_is_teemo_lib::proxy::presence::{self, OfflinePresence, VisibilityRules};
+use where_is_teemo_lib::state::StealthMode;
+
+/// One friend's presence as the Riot client sends it during login.
+fn friend_presence(i: usize) -> String {
+    format!(
+        r#"<presence from="friend{i}@br1.pvp.net/RC-{i}" id="p{i}"><show>chat</show><status>In Game</status><games><keystone><st>chat</st><s.p>riot_client</s.p><p>eyJwdHkiOnRydWV9</p></keystone><league_of_legends><st>dnd</st><s.p>league_of_legends</s.p><p>{{&quot;championId&quot;:&quot;{i}&quot;,&quot;gameStatus&quot;:&quot;inGame&quot;}}</p></league_of_legends></games></presence>"#
+    )
+}
+
+/// Many small stanzas: the presence burst of a large friends list.
+fn presence_burst(count: usize) -> String {
+    (0..count).map(friend_presence).collect()
+}
+
+/// One large stanza: a roster result with `count` items.
+fn roster_result(count: usize) -> String {
+    let items: String = (0..count)
+        .map(|i| {
+            format!(
+                r#"<item jid="friend{i}@br1.pvp.net" name="Friend {i}" subscription="both"><group priority="1">General</group><id name="Friend{i}" tagline="BR1"/></item>"#
+            )
+        })
+        .collect();
+    format!(r#"<iq type="result" id="roster_1"><query xmlns="jabber:iq:roster">{items}</query></iq>"#)
+}
+
+/// Split a buffer the way the proxy's client → server loop does.
+fn split_all(buffer: &str) -> usize {
+    let mut consumed = 0;
+    let mut count = 0;
+    while let Some(end) = presence::find_stanza_end(&buffer[consumed..]) {
+        consumed += end;
+        count += 1;
+    }
+    count
+}
+
+fn bench_splitting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_stanza_end");
+
+    let burst = presence_burst(2000);
+    group.throughput(Throughput::Bytes(burst.len() as u64));
+    group.bench_function("many_small_presences", |b| {
+        b.iter(|| split_all(black_box(&burst)))
+    });
+
+    let roster = roster_result(2000);
+    group.throughput(Throughput::Bytes(roster.len() as u64));
+    group.bench_function("large_roster_result", |b| {
+        b.iter(|| split_all(black_box(&roster)))
+    });
+
+    group.finish();
+}
+
+fn bench_filtering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter_outgoing");
+    let stanzas: Vec<String> = (0..500).map(friend_presence).collect();
+    let bytes: usize = stanzas.iter().map(String::len).sum();
+    group.throughput(Throughput::Bytes(bytes as u64));
+
+    group.bench_function("offline_presences", |b| {
+        b.iter(|| {
+            for stanza in &stanzas {
+                black_box(presence::filter_outgoing(
+                    black_box(stanza),
+                    &StealthMode::Offline,
+                    OfflinePresence::Unavailable,
+                ));
+            }
+        })
+    });
+
+    let rules = VisibilityRules {
+        allow: (0..50).map(|i| format!("friend{i}@br1.pvp.net")).collect(),
+        block: vec!["*@na1.pvp.net".to_string()],
+        ..Default::default()
+    };
+    group.bench_function("offline_presences_with_rules", |b| {
+        b.iter(|| {
+            for stanza in &stanzas {
+                black_box(presence::filter_outgoing_with_rules(
+                    black_box(stanza),
+                    &StealthMode::Offline,
+                    &rules,
+                    OfflinePresence::Unavailable,
+                ));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_splitting, bench_filtering);
+criterion_main!(benches);
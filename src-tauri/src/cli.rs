@@ -0,0 +1,102 @@
+use crate::commands;
+use crate::state::StealthMode;
+
+/// Initial stealth mode from `--mode <online|offline|away|dnd|xa>` (or
+/// `--mode=...`), for scripted starts. An invalid value is logged and ignored
+/// so the app still starts with its default.
+pub fn mode_from_args<I>(args: I) -> Option<StealthMode>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--mode") {
+            Some("") => args.next(),
+            Some(rest) => rest.strip_prefix('=').map(str::to_string),
+            None => continue,
+        };
+        let Some(value) = value else {
+            log::warn!("--mode needs a value (online, offline, away, dnd or xa) — ignoring");
+            return None;
+        };
+        return match commands::parse_mode(&value.to_lowercase()) {
+            Ok(mode) => Some(mode),
+            Err(e) => {
+                log::warn!("{e} — ignoring --mode");
+                None
+            }
+        };
+    }
+    None
+}
+
+/// What a later launch asks of the instance that is already running.
+#[derive(Debug, PartialEq)]
+pub enum LaunchRequest {
+    /// `--toggle`: go invisible, or back to the last visible mode.
+    Toggle,
+    /// `--mode <online|offline|away|dnd|xa>`: switch to that mode.
+    SetMode(StealthMode),
+    /// Anything else: bring the window up.
+    Show,
+}
+
+pub fn launch_request<I>(args: I) -> LaunchRequest
+where
+    I: IntoIterator<Item = String>,
+{
+    let args: Vec<String> = args.into_iter().collect();
+    if args.iter().any(|a| a == "--toggle") {
+        return LaunchRequest::Toggle;
+    }
+    match mode_from_args(args) {
+        Some(mode) => LaunchRequest::SetMode(mode),
+        None => LaunchRequest::Show,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_mode_separate_value() {
+        assert_eq!(
+            mode_from_args(args(&["--hidden", "--mode", "offline"])),
+            Some(StealthMode::Offline)
+        );
+    }
+
+    #[test]
+    fn test_mode_equals_value() {
+        assert_eq!(mode_from_args(args(&["--mode=Online"])), Some(StealthMode::Online));
+        assert_eq!(mode_from_args(args(&["--mode=dnd"])), Some(StealthMode::Dnd));
+    }
+
+    #[test]
+    fn test_mode_invalid_or_missing() {
+        assert_eq!(mode_from_args(args(&["--mode", "busy"])), None);
+        assert_eq!(mode_from_args(args(&["--mode"])), None);
+        assert_eq!(mode_from_args(args(&["--modes=offline"])), None);
+        assert_eq!(mode_from_args(args(&[])), None);
+    }
+
+    #[test]
+    fn test_launch_request() {
+        assert_eq!(launch_request(args(&["--toggle"])), LaunchRequest::Toggle);
+        assert_eq!(
+            launch_request(args(&["--mode", "online", "--toggle"])),
+            LaunchRequest::Toggle
+        );
+        assert_eq!(
+            launch_request(args(&["--mode=offline"])),
+            LaunchRequest::SetMode(StealthMode::Offline)
+        );
+        assert_eq!(launch_request(args(&["--hidden"])), LaunchRequest::Show);
+        assert_eq!(launch_request(args(&["--toggles"])), LaunchRequest::Show);
+    }
+}
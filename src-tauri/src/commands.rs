@@ -1,55 +1,196 @@
-use tauri::{AppHandle, Manager, State};
+use std::time::Duration;
 
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::watch;
+
+use crate::logging;
+use crate::paths;
 use crate::proxy;
-use crate::proxy::certs;
-use crate::proxy::config_proxy;
+use crate::proxy::capture::{self, CapturedStream, TrafficCapture};
+use crate::proxy::certs::{self, CaInstallOutcome};
+use crate::proxy::chat_probe::{self, ChatFailure};
+use crate::proxy::config_proxy::{self, ConfigDump};
+use crate::proxy::injection::InjectionJitter;
+use crate::proxy::mock_server;
+use crate::proxy::presence::{self, OfflinePresence, VisibilityRules};
+use crate::proxy::presence_store;
+use crate::proxy::session::{AccountModes, ConnectionCounter, ConnectionModeEvent, PresenceCache};
+use crate::proxy::xmpp_proxy;
 use crate::riot;
-use crate::state::{AppState, ProxyStatus, StatusInfo, StealthMode};
+use crate::settings::{self, Settings};
+use crate::state::{
+    self, AppState, AppStateInner, ProxyStatus, StatusInfo, StealthMode, StealthPause,
+};
 
 #[tauri::command]
 pub fn get_status(state: State<'_, AppState>) -> StatusInfo {
     let inner = state.inner.lock().unwrap();
-    StatusInfo {
-        stealth_mode: inner.stealth_mode.clone(),
-        proxy_status: inner.proxy_status.clone(),
-        connected_game: inner.connected_game.clone(),
-    }
+    inner.status()
 }
 
+/// Unknown mode strings are rejected rather than read as Offline, so a typo
+/// can't silently change what friends see.
 #[tauri::command]
-pub fn set_stealth_mode(mode: String, state: State<'_, AppState>) -> StatusInfo {
-    let mut inner = state.inner.lock().unwrap();
-    let new_mode = match mode.as_str() {
-        "online" => StealthMode::Online,
-        _ => StealthMode::Offline,
+pub fn set_stealth_mode(
+    mode: String,
+    account: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<StatusInfo, String> {
+    // Targeting one account only overrides that account; "default" removes the
+    // override so it follows the global mode again.
+    let new_mode = match (&account, mode.as_str()) {
+        (Some(_), "default") => None,
+        _ => Some(parse_mode(&mode)?),
     };
+    let mut inner = state.inner.lock().unwrap();
+
+    if let Some(account) = account {
+        inner.account_modes_tx.send_modify(|modes| match new_mode {
+            Some(new_mode) => {
+                log::info!("Stealth mode for {account}: {new_mode:?}");
+                modes.insert(account, new_mode);
+            }
+            None => {
+                log::info!("Stealth mode for {account}: following global mode");
+                modes.remove(&account);
+            }
+        });
+
+        return Ok(inner.status());
+    }
+
+    if let Some(new_mode) = new_mode {
+        apply_stealth_mode(&mut inner, new_mode);
+    }
+    Ok(inner.status())
+}
+
+/// Switch the global stealth mode and push it to the running proxy. Shared by
+/// `set_stealth_mode` and the tray menu. Cancels a running `pause_stealth`.
+pub fn apply_stealth_mode(inner: &mut AppStateInner, new_mode: StealthMode) {
     log::info!("Stealth mode changed: {:?} → {:?}", inner.stealth_mode, new_mode);
-    inner.stealth_mode = new_mode.clone();
+    inner.set_stealth_mode(new_mode.clone());
+    if inner.stealth_pause.take().is_some() {
+        log::info!("Stealth pause cancelled by the mode change");
+    }
 
     if let Some(tx) = &inner.mode_tx {
         let _ = tx.send(new_mode);
     } else {
         log::warn!("No mode channel — proxy not running, mode change won't take effect until next launch");
     }
+}
 
-    StatusInfo {
-        stealth_mode: inner.stealth_mode.clone(),
-        proxy_status: inner.proxy_status.clone(),
-        connected_game: inner.connected_game.clone(),
+/// Longest pause `pause_stealth` accepts.
+const MAX_STEALTH_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+/// Appear Online for `seconds` (the cached presence is restored), then
+/// return to the mode from before the pause. Calling it again restarts the
+/// countdown; changing the mode by hand cancels it. Emits
+/// `stealth-pause-ended` with the new status when the pause runs out.
+#[tauri::command]
+pub fn pause_stealth(
+    seconds: u64,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<StatusInfo, String> {
+    if seconds == 0 || seconds > MAX_STEALTH_PAUSE.as_secs() {
+        return Err(format!(
+            "Pause must be between 1 and {} seconds",
+            MAX_STEALTH_PAUSE.as_secs()
+        ));
+    }
+    let duration = Duration::from_secs(seconds);
+
+    let mut inner = state.inner.lock().unwrap();
+    let resume_mode = match inner.stealth_pause.take() {
+        Some(pause) => pause.resume_mode,
+        None if inner.stealth_mode == StealthMode::Online => {
+            return Err("Already online — there is no stealth to pause".to_string());
+        }
+        None => inner.stealth_mode.clone(),
+    };
+    if inner.stealth_mode != StealthMode::Online {
+        apply_stealth_mode(&mut inner, StealthMode::Online);
+    }
+    inner.pause_generation += 1;
+    let id = inner.pause_generation;
+    log::info!("Stealth paused for {seconds}s, then back to {resume_mode:?}");
+    inner.stealth_pause = Some(StealthPause {
+        id,
+        resume_mode,
+        until: std::time::SystemTime::now() + duration,
+    });
+
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(duration).await;
+        let state = app.state::<AppState>();
+        let mut inner = state.inner.lock().unwrap();
+        // Superseded by a newer pause or cancelled by a mode change
+        let Some(pause) = inner.stealth_pause.take_if(|pause| pause.id == id) else {
+            return;
+        };
+        log::info!("Stealth pause over");
+        apply_stealth_mode(&mut inner, pause.resume_mode);
+        let _ = app.emit("stealth-pause-ended", inner.status());
+    });
+
+    Ok(inner.status())
+}
+
+pub fn parse_mode(mode: &str) -> Result<StealthMode, String> {
+    match mode {
+        "online" => Ok(StealthMode::Online),
+        "offline" => Ok(StealthMode::Offline),
+        "away" => Ok(StealthMode::Away),
+        "dnd" => Ok(StealthMode::Dnd),
+        "xa" => Ok(StealthMode::ExtendedAway),
+        _ => Err(format!("Unknown stealth mode: {mode}")),
     }
 }
 
+/// Prefix of the `launch_game` error returned when the CA isn't trusted, so the
+/// UI can offer to install it (or launch anyway).
+pub const CA_NOT_TRUSTED_ERROR: &str = "ca_not_trusted";
+
+/// How long after launch chat gets to come up through the proxy before
+/// `chat-failed` is emitted. Covers a Riot Client self-update and login.
+const CHAT_PROBE_TIMEOUT: Duration = Duration::from_secs(180);
+
 /// Full launch flow: kill existing → start config proxy → start XMPP proxy → launch game.
+/// Refuses to launch with an untrusted CA unless `ignore_untrusted_ca` is set.
 #[tauri::command]
 pub async fn launch_game(
     game: String,
+    ignore_untrusted_ca: Option<bool>,
     app: AppHandle,
     state: State<'_, AppState>,
 ) -> Result<StatusInfo, String> {
-    let data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {e}"))?;
+    let data_dir = paths::data_dir(&app)?;
+
+    // Everything the launch needs from state, read under one lock so a
+    // concurrent set_region or mode toggle can't leave us with a torn mix.
+    let snapshot = {
+        let inner = state.inner.lock().unwrap();
+        LaunchSnapshot::take(&inner)
+    };
+
+    // Without a trusted CA the client rejects our cert and chat fails silently.
+    // Trust can only be checked on platforms where we install the CA.
+    let trust_checkable = cfg!(any(target_os = "macos", target_os = "windows"));
+    if trust_checkable
+        && !ignore_untrusted_ca.unwrap_or(false)
+        && !certs::is_ca_installed(&data_dir)
+    {
+        log::warn!("Launch blocked: CA certificate is not trusted");
+        return Err(format!(
+            "{CA_NOT_TRUSTED_ERROR}: CA certificate is not trusted — install it before launching"
+        ));
+    }
+
+    // Checked before anything is torn down, so a missing game leaves a running
+    // client alone.
+    riot::process::ensure_game_installed(&game)?;
 
     // 1. Kill existing Riot processes
     if riot::process::is_riot_running() {
@@ -61,42 +202,97 @@ pub async fn launch_game(
     let ca = certs::ensure_ca(&data_dir)?;
     let server = certs::generate_server_cert(&ca, &data_dir)?;
 
-    // 3. Start config proxy (intercepts Riot config, redirects chat to localhost)
-    let config_handle = config_proxy::start_config_proxy(5223).await?;
-    let config_port = config_handle.port;
-    let chat_host_rx = config_handle.chat_host_rx;
+    let LaunchSnapshot {
+        initial_mode,
+        chat_host,
+        upstream_proxy,
+        config_timeout,
+        presence_cache,
+        connections,
+        visibility_rx,
+        jitter_rx,
+        account_modes_rx,
+        fallback_hosts,
+        capture,
+        offline_presence,
+        persist_presence,
+        verify_unavailable_ack,
+        strict_offline,
+        config_proxy_port,
+        upstream_config_url,
+        upstream_keepalive,
+        allow_arbitrary_host,
+        config_headers_rx,
+    } = snapshot;
 
-    // 4. Start XMPP proxy (we'll use a default host, updated when config is fetched)
-    let initial_mode = {
-        let inner = state.inner.lock().unwrap();
-        inner.stealth_mode.clone()
-    };
-
-    // Use selected region's chat host, or default
-    let chat_host = {
-        let inner = state.inner.lock().unwrap();
-        inner.detected_chat_host.clone()
-    }
-    .unwrap_or_else(|| "na2.chat.si.riotgames.com".to_string());
+    presence_cache
+        .persist_to(persist_presence.then(|| presence_store::store_path(&data_dir)));
 
+    // 3. Start XMPP proxy (we'll use a default host, updated when config is fetched)
     log::info!("Using chat host: {chat_host}");
 
     // Start XMPP proxy in Online (passthrough) mode so the Riot Client patcher
     // can reach update servers without interference. Stealth mode is activated
     // later, once the actual game client process is detected.
     let proxy_handle = proxy::start_proxy(
+        xmpp_proxy::ProxyConfig {
+            listen_addr: "127.0.0.1:5223".to_string(),
+            remote_port: 5223,
+            server_cert_pem: server.cert_pem,
+            server_key_pem: server.key_pem,
+            ca_cert_pem: ca.cert_pem,
+            trust_local_ca: false,
+            upstream_proxy: upstream_proxy.clone(),
+            presence_cache,
+            visibility_rx,
+            jitter_rx,
+            account_modes_rx,
+            fallback_hosts,
+            connections: connections.clone(),
+            capture,
+            offline_presence,
+            verify_unavailable_ack,
+            strict_offline,
+            upstream_keepalive,
+            allow_arbitrary_host,
+        },
         chat_host,
-        5223,
-        server.cert_pem,
-        server.key_pem,
-        ca.cert_pem,
         StealthMode::Online,
     )
     .await?;
 
+    // 4. Start config proxy (intercepts Riot config, redirects chat to localhost).
+    // chat.port is patched to the port the XMPP proxy actually bound, so the
+    // two can't disagree.
+    let config_handle = match config_proxy::start_config_proxy(
+        config_proxy_port.unwrap_or(0),
+        proxy_handle.listen_addr.port(),
+        upstream_config_url,
+        upstream_proxy,
+        config_timeout,
+        config_headers_rx,
+    )
+    .await
+    {
+        Ok(handle) => handle,
+        Err(e) => {
+            let _ = proxy_handle.shutdown_tx.send(true);
+            return Err(e);
+        }
+    };
+    let config_port = config_handle.port;
+    let chat_host_rx = config_handle.chat_host_rx;
+    let config_requests_rx = config_handle.requests_rx;
+    let last_config_rx = config_handle.last_config_rx;
+    let config_stats_rx = config_handle.stats_rx;
+    let probe_config_stats_rx = config_stats_rx.clone();
+    let mut first_request_rx = config_handle.first_request_rx;
+    let mut clock_skew_rx = config_handle.clock_skew_rx;
+    let chat_host_refresher = config_handle.refresher;
+
     // 5. Launch the game with our config proxy
     log::info!("Launching game '{game}' via config proxy on port {config_port}");
-    if let Err(e) = riot::process::launch_riot_client(&game, config_port) {
+    if let Err(e) = riot::process::launch_riot_client(&game, Some(config_port)) {
         log::error!("Failed to launch game: {e}");
         // Clean up proxies since launch failed
         let _ = proxy_handle.shutdown_tx.send(true);
@@ -114,7 +310,17 @@ pub async fn launch_game(
         inner.connected_game = Some(game);
         inner.mode_tx = Some(proxy_handle.mode_tx);
         inner.shutdown_tx = Some(proxy_handle.shutdown_tx);
+        inner.proxy_ready_rx = Some(proxy_handle.ready_rx.clone());
+        inner.chat_disconnected_tx = Some(proxy_handle.chat_disconnected_tx);
+        inner.upstream_roots = Some(proxy_handle.upstream_roots);
+        inner.reapply_tx = Some(proxy_handle.reapply_tx);
         inner.config_shutdown_tx = Some(config_handle.shutdown_tx);
+        inner.config_port = Some(config_port);
+        inner.config_requests_rx = Some(config_requests_rx);
+        inner.last_config_rx = Some(last_config_rx);
+        inner.config_stats_rx = Some(config_stats_rx);
+        inner.host_tx = Some(proxy_handle.host_tx.clone());
+        inner.chat_host_refresher = Some(chat_host_refresher);
     }
 
     // 7. Spawn a task to update XMPP proxy target once real chat host is discovered
@@ -130,9 +336,111 @@ pub async fn launch_game(
         }
     });
 
-    // 8. Once the actual game client starts, activate the user's desired stealth mode.
+    // 8. Track chat client connections so the UI can tell "proxy running, no
+    // client" apart from "proxy running, client connected".
+    let mut connections_rx = connections.subscribe();
+    let mut ready_rx = proxy_handle.ready_rx;
+    let app_for_connections = app.clone();
+    tokio::spawn(async move {
+        let mut previous = *connections_rx.borrow_and_update();
+        loop {
+            tokio::select! {
+                result = connections_rx.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                // The counter outlives the proxy; stop watching once it shuts down
+                result = ready_rx.changed() => {
+                    if result.is_err() || ready_rx.borrow().is_none() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let count = *connections_rx.borrow_and_update();
+            if previous > 0 && count == 0 {
+                log::info!("Last chat client disconnected");
+                let _ = app_for_connections.emit("client-disconnected", ());
+            } else if previous == 0 && count > 0 {
+                let _ = app_for_connections.emit("client-connected", ());
+            }
+            previous = count;
+        }
+    });
+
+    // 9. Surface TLS interception (antivirus HTTPS scanning) on the chat
+    // connection, which otherwise shows up as chat silently failing.
+    let mut interference_rx = proxy_handle.interference_rx;
+    let app_for_interference = app.clone();
+    tokio::spawn(async move {
+        while interference_rx.changed().await.is_ok() {
+            let issuer = interference_rx.borrow_and_update().clone();
+            if let Some(issuer) = issuer {
+                let _ = app_for_interference.emit("tls-interference-detected", issuer);
+            }
+        }
+    });
+
+    // 10. Evidence the client took our config URL rather than Riot's
+    let app_for_config = app.clone();
+    tokio::spawn(async move {
+        while first_request_rx.changed().await.is_ok() {
+            let user_agent = first_request_rx.borrow_and_update().clone();
+            if let Some(user_agent) = user_agent {
+                let _ = app_for_config.emit("config-proxy-first-request", user_agent);
+                break;
+            }
+        }
+    });
+
+    // 11. A wrong system clock breaks TLS with misleading "expired" or "not
+    // yet valid" errors; say so up front
+    let app_for_clock = app.clone();
+    tokio::spawn(async move {
+        let skew = clock_skew_rx.wait_for(Option::is_some).await.map(|s| s.unwrap_or_default());
+        if let Ok(skew) = skew {
+            let _ = app_for_clock.emit("clock-skew-detected", skew);
+        }
+    });
+
+    // 12. Confirm chat actually came up through the proxy, or say why not
+    let mut probe_rx = proxy_handle.probe_rx;
+    let app_for_probe = app.clone();
+    tokio::spawn(async move {
+        let tunnel = probe_rx.wait_for(|p| p.tunnel_host.is_some());
+        let host = tokio::time::timeout(CHAT_PROBE_TIMEOUT, tunnel)
+            .await
+            .map(|r| r.map(|probe| probe.tunnel_host.clone().unwrap_or_default()));
+        match host {
+            Ok(Ok(host)) => {
+                log::info!("Chat is up through the proxy ({host})");
+                let _ = app_for_probe.emit("chat-connected", host);
+            }
+            // Proxy stopped before chat came up
+            Ok(Err(_)) => {}
+            Err(_) => {
+                let config = *probe_config_stats_rx.borrow();
+                if let Some(failed) = chat_probe::failure_event(&probe_rx.borrow(), &config) {
+                    if failed.cause == ChatFailure::ChatUnavailable {
+                        log::info!("{}", failed.message);
+                    } else {
+                        log::warn!(
+                            "Chat didn't come up within {}s: {}",
+                            CHAT_PROBE_TIMEOUT.as_secs(),
+                            failed.message
+                        );
+                    }
+                    let _ = app_for_probe.emit("chat-failed", failed);
+                }
+            }
+        }
+    });
+
+    // 13. Once the actual game client starts, activate the user's desired stealth mode.
     // This avoids interfering with the Riot Client patcher during the update phase.
-    if initial_mode == StealthMode::Offline {
+    if initial_mode != StealthMode::Online {
         tokio::spawn(async move {
             let start = std::time::Instant::now();
             loop {
@@ -148,9 +456,9 @@ pub async fn launch_game(
                     let s = app_for_task.state::<AppState>();
                     let inner = s.inner.lock().unwrap();
                     // Respect any mode change the user may have made while waiting
-                    if inner.stealth_mode == StealthMode::Offline {
+                    if inner.stealth_mode == initial_mode {
                         if let Some(tx) = &inner.mode_tx {
-                            let _ = tx.send(StealthMode::Offline);
+                            let _ = tx.send(initial_mode);
                         }
                     }
                     break;
@@ -160,84 +468,987 @@ pub async fn launch_game(
     }
 
     let inner = state.inner.lock().unwrap();
-    Ok(StatusInfo {
-        stealth_mode: inner.stealth_mode.clone(),
-        proxy_status: inner.proxy_status.clone(),
-        connected_game: inner.connected_game.clone(),
+    Ok(inner.status())
+}
+
+/// Ask Riot for the current chat host and, if it moved since launch, point the
+/// running XMPP proxy at the new one. New connections use it; the client
+/// reconnects on its own once chat drops.
+#[tauri::command]
+pub async fn refresh_chat_host(state: State<'_, AppState>) -> Result<ChatHostRefresh, String> {
+    let (refresher, host_tx) = {
+        let inner = state.inner.lock().unwrap();
+        match (inner.chat_host_refresher.clone(), inner.host_tx.clone()) {
+            (Some(refresher), Some(host_tx)) => (refresher, host_tx),
+            _ => return Err("Proxy is not running".to_string()),
+        }
+    };
+
+    let current = refresher.fetch_chat_host().await?;
+    let previous = host_tx.borrow().clone();
+    let changed = current != previous;
+    if changed {
+        log::info!("Chat host moved: {previous} → {current} — updating XMPP proxy target");
+        host_tx.send_replace(current.clone());
+        state.inner.lock().unwrap().detected_chat_host = Some(current.clone());
+    } else {
+        log::info!("Chat host {current} is still current");
+    }
+
+    Ok(ChatHostRefresh {
+        previous,
+        current,
+        changed,
     })
 }
 
+/// Launch the game with Riot's real config and no proxies — stealth is off.
+/// An escape hatch when stealth misbehaves and an A/B check for proxy issues.
 #[tauri::command]
-pub fn stop_proxy(state: State<'_, AppState>) -> StatusInfo {
-    let mut inner = state.inner.lock().unwrap();
+pub fn launch_game_direct(game: String, state: State<'_, AppState>) -> Result<StatusInfo, String> {
+    // Checked before anything is torn down, so a missing game leaves a running
+    // stealth session alone.
+    riot::process::ensure_game_installed(&game)?;
 
-    if let Some(tx) = inner.shutdown_tx.take() {
-        let _ = tx.send(true);
+    {
+        let mut inner = state.inner.lock().unwrap();
+        state::teardown_proxy(&mut inner);
     }
-    if let Some(tx) = inner.config_shutdown_tx.take() {
-        let _ = tx.send(true);
+
+    if riot::process::is_riot_running() {
+        log::info!("Killing existing Riot processes");
+        riot::process::kill_riot_processes()?;
     }
-    inner.mode_tx = None;
-    inner.proxy_status = ProxyStatus::Idle;
-    inner.connected_game = None;
 
-    StatusInfo {
-        stealth_mode: inner.stealth_mode.clone(),
-        proxy_status: inner.proxy_status.clone(),
-        connected_game: inner.connected_game.clone(),
+    log::info!("Launching game '{game}' directly, without proxies");
+    riot::process::launch_riot_client(&game, None)?;
+
+    let inner = state.inner.lock().unwrap();
+    Ok(inner.status())
+}
+
+/// Run the XMPP proxy against a built-in mock chat server instead of Riot's.
+/// Point any XMPP client at `127.0.0.1:5223` (trusting our CA) to watch
+/// presence get filtered without sending anything to Riot.
+#[tauri::command]
+pub async fn start_test_mode(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<StatusInfo, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let ca = certs::ensure_ca(&data_dir)?;
+    let server = certs::generate_server_cert(&ca, &data_dir)?;
+
+    {
+        let mut inner = state.inner.lock().unwrap();
+        state::teardown_proxy(&mut inner);
+    }
+
+    let mock = mock_server::start_mock_server(&server.cert_pem, &server.key_pem).await?;
+
+    let (initial_mode, config) = {
+        let inner = state.inner.lock().unwrap();
+        let config = xmpp_proxy::ProxyConfig {
+            listen_addr: "127.0.0.1:5223".to_string(),
+            remote_port: mock.port,
+            server_cert_pem: server.cert_pem,
+            server_key_pem: server.key_pem,
+            ca_cert_pem: ca.cert_pem,
+            trust_local_ca: true,
+            upstream_proxy: None,
+            presence_cache: inner.presence_cache.clone(),
+            visibility_rx: inner.visibility_tx.subscribe(),
+            jitter_rx: inner.jitter_tx.subscribe(),
+            account_modes_rx: inner.account_modes_tx.subscribe(),
+            fallback_hosts: Vec::new(),
+            connections: inner.connections.clone(),
+            capture: inner.capture.clone(),
+            offline_presence: inner.settings.offline_presence,
+            verify_unavailable_ack: inner.settings.verify_unavailable_ack,
+            strict_offline: inner.settings.strict_offline,
+            upstream_keepalive: inner.settings.upstream_keepalive_secs.map(Duration::from_secs),
+            // The mock server is on localhost
+            allow_arbitrary_host: true,
+        };
+        (inner.stealth_mode.clone(), config)
+    };
+
+    let proxy_handle = match proxy::start_proxy(config, "localhost".to_string(), initial_mode).await
+    {
+        Ok(h) => h,
+        Err(e) => {
+            let _ = mock.shutdown_tx.send(true);
+            return Err(e);
+        }
+    };
+
+    log::info!("Test mode: XMPP proxy → mock server on port {}", mock.port);
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.proxy_status = ProxyStatus::Running;
+    inner.connected_game = Some("test_mode".to_string());
+    inner.mode_tx = Some(proxy_handle.mode_tx);
+    inner.shutdown_tx = Some(proxy_handle.shutdown_tx);
+    inner.proxy_ready_rx = Some(proxy_handle.ready_rx);
+    inner.chat_disconnected_tx = Some(proxy_handle.chat_disconnected_tx);
+    inner.upstream_roots = Some(proxy_handle.upstream_roots);
+    inner.reapply_tx = Some(proxy_handle.reapply_tx);
+    inner.mock_shutdown_tx = Some(mock.shutdown_tx);
+    Ok(inner.status())
+}
+
+#[tauri::command]
+pub fn stop_proxy(state: State<'_, AppState>) -> StatusInfo {
+    let mut inner = state.inner.lock().unwrap();
+
+    state::teardown_proxy(&mut inner);
+
+    inner.status()
+}
+
+/// Troubleshooting reset: stop the proxies, forget the detected region/host and
+/// stored presence, and optionally regenerate the certificates from scratch.
+#[tauri::command]
+pub fn reset_state(
+    regenerate_certs: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<StatusInfo, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let mut inner = state.inner.lock().unwrap();
+
+    state::teardown_proxy(&mut inner);
+    inner.detected_region = None;
+    inner.detected_chat_host = None;
+    inner.account_modes_tx.send_replace(Default::default());
+    presence_store::clear(&presence_store::store_path(&data_dir))?;
+
+    log::info!("State reset to defaults");
+
+    if regenerate_certs.unwrap_or(false) {
+        certs::remove_certs(&data_dir)?;
+        let ca = certs::ensure_ca(&data_dir)?;
+        certs::generate_server_cert(&ca, &data_dir)?;
+        log::warn!("Certificates regenerated — the new CA must be installed again");
     }
+
+    Ok(inner.status())
+}
+
+/// Sever chat entirely: close every proxied chat connection and refuse new
+/// ones until `reconnect_chat`, leaving the proxy and the game running. The
+/// client shows chat as disconnected — harder stealth than presence
+/// rewriting, for when filtering doesn't hold for an account.
+#[tauri::command]
+pub fn disconnect_chat(state: State<'_, AppState>) -> Result<StatusInfo, String> {
+    set_chat_disconnected(&state, true)
+}
+
+/// Let chat connections through again after `disconnect_chat`. The client
+/// reconnects on its own retry schedule.
+#[tauri::command]
+pub fn reconnect_chat(state: State<'_, AppState>) -> Result<StatusInfo, String> {
+    set_chat_disconnected(&state, false)
+}
+
+fn set_chat_disconnected(state: &AppState, disconnected: bool) -> Result<StatusInfo, String> {
+    let inner = state.inner.lock().unwrap();
+    let tx = inner
+        .chat_disconnected_tx
+        .as_ref()
+        .ok_or("Proxy is not running")?;
+    tx.send_replace(disconnected);
+    log::info!("Chat {}", if disconnected { "disconnected" } else { "reconnected" });
+    Ok(inner.status())
+}
+
+/// Whether the XMPP proxy has actually bound its listener.
+#[tauri::command]
+pub fn get_proxy_ready(state: State<'_, AppState>) -> bool {
+    let inner = state.inner.lock().unwrap();
+    inner
+        .proxy_ready_rx
+        .as_ref()
+        .is_some_and(|rx| rx.borrow().is_some())
 }
 
 #[tauri::command]
 pub fn get_cert_status(app: AppHandle) -> Result<CertStatus, String> {
-    let data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {e}"))?;
+    let data_dir = paths::data_dir(&app)?;
+    Ok(cert_status(&data_dir))
+}
 
+fn cert_status(data_dir: &std::path::Path) -> CertStatus {
     let ca_exists = data_dir.join("certs").join("ca.pem").exists();
-    let server_exists = data_dir.join("certs").join("server.pem").exists();
-    let ca_trusted = certs::is_ca_installed(&data_dir);
+    // A server cert signed by a since-regenerated CA is as good as missing
+    let server_exists = certs::server_cert_matches_ca(data_dir);
+    let ca_trusted = certs::is_ca_installed(data_dir);
 
-    Ok(CertStatus {
+    CertStatus {
         ca_generated: ca_exists,
         server_generated: server_exists,
         ca_trusted,
-    })
+    }
+}
+
+/// The fully resolved configuration the app is running with: region and
+/// chat hosts as resolved, listen ports, filtering settings, cert trust and
+/// the data dir. Unlike `get_status`, this is what the app is configured to
+/// do rather than what it is doing. Proxy credentials are redacted.
+#[tauri::command]
+pub fn get_effective_config(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<EffectiveConfig, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let certs = cert_status(&data_dir);
+
+    let inner = state.inner.lock().unwrap();
+    Ok(effective_config(&inner, &data_dir, certs))
+}
+
+fn effective_config(
+    inner: &AppStateInner,
+    data_dir: &std::path::Path,
+    certs: CertStatus,
+) -> EffectiveConfig {
+    EffectiveConfig {
+        data_dir: data_dir.display().to_string(),
+        stealth_mode: inner.stealth_mode.clone(),
+        region: inner.detected_region.clone(),
+        chat_host: resolved_chat_host(inner),
+        live_chat_host: inner.host_tx.as_ref().map(|tx| tx.borrow().clone()),
+        fallback_chat_hosts: fallback_chat_hosts(inner),
+        xmpp_listen_addr: inner.status().listen_addr,
+        config_proxy_port: inner.config_port,
+        upstream_config_url: redact_proxy_url(&upstream_config_url(inner)),
+        upstream_proxy: inner.upstream_proxy.as_deref().map(redact_proxy_url),
+        upstream_roots: inner.upstream_roots.clone(),
+        visibility_rules: inner.visibility_tx.borrow().clone(),
+        settings: Settings {
+            upstream_config_url: inner
+                .settings
+                .upstream_config_url
+                .as_deref()
+                .map(redact_proxy_url),
+            ..inner.settings.clone()
+        },
+        certs,
+    }
+}
+
+/// Write everything a bug report needs to `path` as JSON: version, effective
+/// config, status and config stats, the connection mode log, the last chat
+/// config, the last presence sent and, while a capture runs, the most recent
+/// traffic. Secrets are redacted throughout, and the file is replaced in one
+/// step so a failed export never leaves half a report behind.
+#[tauri::command]
+pub fn export_diagnostics_file(
+    path: String,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+    let certs = cert_status(&data_dir);
+    let export = {
+        let inner = state.inner.lock().unwrap();
+        DiagnosticsExport {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            exported_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            config: effective_config(&inner, &data_dir, certs),
+            status: inner.status(),
+            connection_mode_log: inner.connections.mode_log(),
+            last_config: inner.last_config_rx.as_ref().and_then(|rx| rx.borrow().clone()),
+            last_sent_presence: inner.presence_cache.last_sent(None).map(|p| capture::redact(&p)),
+            recent_traffic: inner.capture.recent(),
+        }
+    };
+    let json = serde_json::to_string_pretty(&export)
+        .map_err(|e| format!("Failed to serialize diagnostics: {e}"))?;
+    paths::write_atomic(std::path::Path::new(&path), json.as_bytes())?;
+    log::info!("Diagnostics exported to {path}");
+    Ok(())
+}
+
+/// A proxy or config URL with any username and password replaced by `***`.
+fn redact_proxy_url(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url) else {
+        return "***".to_string();
+    };
+    if !parsed.username().is_empty() {
+        let _ = parsed.set_username("***");
+    }
+    if parsed.password().is_some() {
+        let _ = parsed.set_password(Some("***"));
+    }
+    parsed.to_string()
+}
+
+/// The last chat config the config proxy patched, as Riot sent it and as the
+/// client received it, with auth-bearing fields redacted. None until a
+/// launch has fetched chat config.
+#[tauri::command]
+pub fn get_last_config(state: State<'_, AppState>) -> Option<ConfigDump> {
+    let inner = state.inner.lock().unwrap();
+    inner.last_config_rx.as_ref().and_then(|rx| rx.borrow().clone())
 }
 
 #[tauri::command]
 pub fn install_ca(app: AppHandle) -> Result<(), String> {
-    let data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {e}"))?;
+    let data_dir = paths::data_dir(&app)?;
 
     certs::install_ca_system(&data_dir)
 }
 
+/// Guided onboarding in one call: make sure the certs exist, get the CA
+/// trusted, and pick up the region from the local Riot Client if none is
+/// selected. The CA is only installed here (with an OS admin prompt) when
+/// `auto_install_ca` is on; otherwise trusting it is left in `needs_action`.
+/// Safe to call again; finished steps are skipped. Failures don't abort the
+/// rest — they're listed in `needs_action`.
 #[tauri::command]
-pub fn get_regions() -> Vec<RegionInfo> {
-    riot::config::REGIONS
+pub fn first_run_setup(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<FirstRunReport, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let mut needs_action = Vec::new();
+    let auto_install_ca = state.inner.lock().unwrap().settings.auto_install_ca;
+
+    let certs_ready = match certs::ensure_ca(&data_dir) {
+        Ok(_) if certs::server_cert_matches_ca(&data_dir) => true,
+        Ok(ca) => match certs::generate_server_cert(&ca, &data_dir) {
+            Ok(_) => true,
+            Err(e) => {
+                needs_action.push(format!("Server certificate: {e}"));
+                false
+            }
+        },
+        Err(e) => {
+            needs_action.push(format!("CA certificate: {e}"));
+            false
+        }
+    };
+
+    let trust_checkable = cfg!(any(target_os = "macos", target_os = "windows"));
+    let mut ca_trusted = certs::is_ca_installed(&data_dir);
+    let mut ca_install = if ca_trusted {
+        CaInstallOutcome::AlreadyTrusted
+    } else {
+        CaInstallOutcome::Skipped
+    };
+    if certs_ready && trust_checkable && !ca_trusted {
+        if auto_install_ca {
+            log::info!("CA not trusted — installing it automatically");
+            ca_install = match certs::install_ca_system(&data_dir) {
+                Ok(()) => {
+                    ca_trusted = certs::is_ca_installed(&data_dir);
+                    CaInstallOutcome::Installed
+                }
+                Err(e) if certs::install_was_cancelled(&e) => {
+                    log::warn!("Automatic CA install cancelled: {e}");
+                    needs_action.push(
+                        "CA installation was cancelled — install the CA certificate to finish \
+                         setup"
+                            .to_string(),
+                    );
+                    CaInstallOutcome::Cancelled
+                }
+                Err(e) => {
+                    log::error!("Automatic CA install failed: {e}");
+                    needs_action.push(format!("Trust the CA certificate: {e}"));
+                    CaInstallOutcome::Failed
+                }
+            };
+        } else {
+            needs_action.push("Trust the CA certificate (install it from the app)".to_string());
+        }
+    }
+    if !trust_checkable {
+        needs_action.push("Trust the CA certificate manually on this platform".to_string());
+    }
+
+    let mut inner = state.inner.lock().unwrap();
+    if inner.settings.first_run && onboarding_complete(&data_dir) {
+        finish_first_run(&mut inner, &data_dir);
+    }
+    if inner.detected_region.is_none() {
+        match riot::client_settings::read_region() {
+            Some(region) => match inner.regions.chat_server_for_region(&region) {
+                Some(host) => {
+                    log::info!("Region '{region}' detected from the Riot Client settings");
+                    inner.detected_chat_host = Some(host.to_string());
+                    inner.detected_region = Some(region);
+                }
+                None => {
+                    needs_action.push(format!("Unknown region '{region}' — select it manually"));
+                }
+            },
+            None => needs_action.push("Region not detected — select it manually".to_string()),
+        }
+    }
+
+    Ok(FirstRunReport {
+        certs_ready,
+        ca_trusted,
+        ca_install,
+        region: inner.detected_region.clone(),
+        needs_action,
+    })
+}
+
+/// Whether the setup the window guides the user through is done: certs in
+/// place and, where it can be checked, the CA trusted.
+pub fn onboarding_complete(data_dir: &std::path::Path) -> bool {
+    let trust_checkable = cfg!(any(target_os = "macos", target_os = "windows"));
+    certs::server_cert_matches_ca(data_dir)
+        && (!trust_checkable || certs::is_ca_installed(data_dir))
+}
+
+/// End the first run: from now on the app starts in the tray.
+pub fn finish_first_run(inner: &mut AppStateInner, data_dir: &std::path::Path) {
+    inner.settings.first_run = false;
+    log::info!("Onboarding complete — future starts stay in the tray");
+    if let Err(e) = settings::save(data_dir, &inner.settings) {
+        log::error!("Failed to save settings: {e}");
+    }
+}
+
+/// Install the CA (with an OS admin prompt) during `first_run_setup` when it
+/// isn't trusted yet. Off by default so setup never prompts unexpectedly.
+#[tauri::command]
+pub fn set_auto_install_ca(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    log::info!("Automatic CA install {}", if enabled { "enabled" } else { "disabled" });
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.auto_install_ca = enabled;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Open the window at every start instead of only on the first run.
+#[tauri::command]
+pub fn set_show_window_on_start(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.show_window_on_start = enabled;
+    log::info!("Show window on start {}", if enabled { "enabled" } else { "disabled" });
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Turn the periodic CA trust check (`ca-trust-lost`) on or off. Applies on
+/// the next start.
+#[tauri::command]
+pub fn set_ca_trust_check(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.ca_trust_check = enabled;
+    log::info!("CA trust check {}", if enabled { "enabled" } else { "disabled" });
+    settings::save(&data_dir, &inner.settings)
+}
+
+#[tauri::command]
+pub fn get_regions(state: State<'_, AppState>) -> Vec<RegionInfo> {
+    let inner = state.inner.lock().unwrap();
+    region_infos(&inner.regions)
+}
+
+/// Re-read `regions.json` from the data dir, e.g. after fixing a changed host.
+#[tauri::command]
+pub fn reload_regions(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<RegionInfo>, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let regions = riot::config::load_regions(&data_dir);
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.regions = regions;
+    Ok(region_infos(&inner.regions))
+}
+
+fn region_infos(regions: &riot::config::RegionTable) -> Vec<RegionInfo> {
+    regions
+        .entries()
         .iter()
-        .map(|(code, name)| RegionInfo {
-            code: code.to_string(),
-            name: name.to_string(),
+        .map(|r| RegionInfo {
+            code: r.code.clone(),
+            name: r.name.clone(),
         })
         .collect()
 }
 
 #[tauri::command]
 pub fn set_region(region: String, state: State<'_, AppState>) -> Result<(), String> {
-    let chat_host = riot::config::chat_server_for_region(&region)
-        .ok_or_else(|| format!("Unknown region: {region}"))?;
-
     let mut inner = state.inner.lock().unwrap();
+    let chat_host = inner
+        .regions
+        .chat_server_for_region(&region)
+        .ok_or_else(|| format!("Unknown region: {region}"))?
+        .to_string();
+
     inner.detected_region = Some(region);
-    inner.detected_chat_host = Some(chat_host.to_string());
+    inner.detected_chat_host = Some(chat_host);
+    Ok(())
+}
+
+/// Route outgoing traffic through an upstream proxy (`http://`, `https://` or
+/// `socks5://`). Pass `None` to connect directly. Applies on the next launch.
+#[tauri::command]
+pub fn set_upstream_proxy(url: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    let url = url.map(|u| u.trim().to_string()).filter(|u| !u.is_empty());
+
+    if let Some(u) = &url {
+        let parsed = reqwest::Url::parse(u).map_err(|e| format!("Invalid proxy URL '{u}': {e}"))?;
+        if !matches!(parsed.scheme(), "http" | "https" | "socks5" | "socks5h") {
+            return Err(format!("Unsupported proxy scheme: {}", parsed.scheme()));
+        }
+    }
+
+    log::info!("Upstream proxy set to {:?}", url);
+    let mut inner = state.inner.lock().unwrap();
+    inner.upstream_proxy = url;
+    Ok(())
+}
+
+/// Replace the per-contact visibility rules. Takes effect immediately,
+/// including on a running proxy.
+#[tauri::command]
+pub fn set_visibility_rules(rules: VisibilityRules, state: State<'_, AppState>) {
+    log::info!(
+        "Visibility rules updated: {} allowed, {} blocked, default {:?}",
+        rules.allow.len(),
+        rules.block.len(),
+        rules.default_action
+    );
+    let inner = state.inner.lock().unwrap();
+    inner.visibility_tx.send_replace(rules);
+}
+
+#[tauri::command]
+pub fn get_settings(state: State<'_, AppState>) -> Settings {
+    let inner = state.inner.lock().unwrap();
+    inner.settings.clone()
+}
+
+/// When disabled, the window close button quits the app instead of hiding it.
+#[tauri::command]
+pub fn set_close_to_tray(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.close_to_tray = enabled;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Ordered chat hosts the proxy falls back to when the detected host is
+/// unreachable. An empty list derives them from the selected region.
+/// Hosts outside Riot's domains are rejected unless arbitrary chat hosts are
+/// allowed. Applies on the next launch.
+#[tauri::command]
+pub fn set_chat_host_candidates(
+    hosts: Vec<String>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let hosts: Vec<String> = hosts
+        .iter()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .collect();
+
+    let mut inner = state.inner.lock().unwrap();
+    let allow_arbitrary = inner.settings.allow_arbitrary_chat_host;
+    for host in &hosts {
+        xmpp_proxy::check_chat_host(host, allow_arbitrary)?;
+    }
+
+    log::info!("Chat host candidates set to {:?}", hosts);
+    inner.settings.chat_host_candidates = hosts;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Let the proxy connect to chat hosts outside Riot's domains. Off by
+/// default: with it on, a tampered config or setting can point the proxy at
+/// any host, including ones on the local network. Applies on the next launch.
+#[tauri::command]
+pub fn set_allow_arbitrary_chat_host(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    if enabled {
+        log::warn!(
+            "Arbitrary chat hosts allowed: the proxy will connect to hosts outside \
+             Riot's domains. Only enable this for a chat server you trust."
+        );
+    } else {
+        log::info!("Chat hosts restricted to Riot's domains");
+    }
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.allow_arbitrary_chat_host = enabled;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// How long the config proxy waits for Riot's config server (1–300 s).
+/// Applies on the next launch.
+#[tauri::command]
+pub fn set_config_timeout(
+    seconds: u64,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if !(1..=300).contains(&seconds) {
+        return Err(format!("Config timeout must be between 1 and 300 seconds, got {seconds}"));
+    }
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.config_timeout_secs = seconds;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// How Offline mode treats broadcast vs directed presence. Applies on the
+/// next launch.
+#[tauri::command]
+pub fn set_offline_presence(
+    policy: OfflinePresence,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.offline_presence = policy;
+    log::info!("Offline presence policy set to {policy:?}");
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Keep the last presence on disk across restarts. Turning it off deletes
+/// the stored copy.
+#[tauri::command]
+pub fn set_persist_presence(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+    let path = presence_store::store_path(&data_dir);
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.persist_presence = enabled;
+    if inner.proxy_status == ProxyStatus::Running {
+        inner.presence_cache.persist_to(enabled.then(|| path.clone()));
+    }
+    if !enabled {
+        presence_store::clear(&path)?;
+    }
+    log::info!("Presence persistence {}", if enabled { "enabled" } else { "disabled" });
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Opt-in check that the server echoes injected unavailable presence. Applies
+/// on the next launch.
+#[tauri::command]
+pub fn set_verify_unavailable_ack(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.verify_unavailable_ack = enabled;
+    log::info!("Unavailable ack check {}", if enabled { "enabled" } else { "disabled" });
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Strict offline: while invisible, also strip typing notifications and
+/// receipts from outgoing messages. Applies on the next launch.
+#[tauri::command]
+pub fn set_strict_offline(
+    enabled: bool,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.strict_offline = enabled;
+    log::info!("Strict offline {}", if enabled { "enabled" } else { "disabled" });
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Run the config proxy on a fixed port instead of a random one (`None`).
+/// The port must then be allowed in any local firewall. Applies on the next
+/// launch.
+#[tauri::command]
+pub fn set_config_proxy_port(
+    port: Option<u16>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    match port {
+        Some(0) => return Err("Port 0 is not a fixed port".to_string()),
+        Some(5223) => return Err("Port 5223 is used by the chat proxy".to_string()),
+        _ => {}
+    }
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.config_proxy_port = port;
+    match port {
+        Some(port) => log::info!("Config proxy will listen on fixed port {port}"),
+        None => log::info!("Config proxy will listen on a random port"),
+    }
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Proxy config requests to `url` instead of Riot's config server (`None`
+/// restores it). `$WIT_CONFIG_URL` overrides this. Applies on the next launch.
+#[tauri::command]
+pub fn set_upstream_config_url(
+    url: Option<String>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let url = url.map(|u| u.trim().to_string()).filter(|u| !u.is_empty());
+    if let Some(u) = &url {
+        let parsed = reqwest::Url::parse(u).map_err(|e| format!("Invalid config URL '{u}': {e}"))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("Unsupported config URL scheme: {}", parsed.scheme()));
+        }
+    }
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    log::info!("Upstream config server set to {:?}", url);
+    inner.settings.upstream_config_url = url;
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Send a whitespace keepalive to the chat server after `secs` idle seconds
+/// (`None` turns it off). Applies on the next launch.
+#[tauri::command]
+pub fn set_upstream_keepalive(
+    secs: Option<u64>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if secs == Some(0) {
+        return Err("Keepalive interval must be at least 1 second".to_string());
+    }
+    let data_dir = paths::data_dir(&app)?;
+
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.upstream_keepalive_secs = secs;
+    match secs {
+        Some(secs) => log::info!("Upstream keepalive every {secs}s of client idle time"),
+        None => log::info!("Upstream keepalive disabled"),
+    }
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// Local API details of the running Riot Client, if its lockfile exists.
+#[tauri::command]
+pub fn get_riot_lockfile() -> Option<riot::lockfile::Lockfile> {
+    riot::lockfile::read_lockfile()
+}
+
+/// Accounts the Riot Client has a persisted login for, to pick per-account
+/// stealth overrides from. Empty if none can be read.
+#[tauri::command]
+pub fn get_riot_accounts() -> Vec<riot::accounts::RiotAccount> {
+    riot::accounts::read_accounts()
+}
+
+/// Request headers the config proxy forwards to Riot.
+#[tauri::command]
+pub fn get_config_headers(state: State<'_, AppState>) -> Vec<String> {
+    state.inner.lock().unwrap().config_headers_tx.borrow().clone()
+}
+
+/// Replace the request headers the config proxy forwards to Riot, for when
+/// Riot starts requiring a new one. Applies immediately, including on a
+/// running proxy. An empty list restores the built-in headers.
+#[tauri::command]
+pub fn set_config_headers(
+    headers: Vec<String>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let data_dir = paths::data_dir(&app)?;
+    let headers = config_proxy::normalize_forwarded_headers(headers)?;
+    let is_default = headers == config_proxy::DEFAULT_FORWARDED_HEADERS;
+
+    for default in config_proxy::DEFAULT_FORWARDED_HEADERS {
+        if !headers.iter().any(|h| h == default) {
+            log::warn!("Config proxy will no longer forward '{default}'");
+        }
+    }
+    log::info!("Config proxy forwarded headers set to {:?}", headers);
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.config_headers = (!is_default).then(|| headers.clone());
+    inner.config_headers_tx.send_replace(headers.clone());
+    settings::save(&data_dir, &inner.settings)?;
+    Ok(headers)
+}
+
+/// Random delay (in ms) before injecting presence on a mode toggle.
+/// `0, 0` injects immediately.
+#[tauri::command]
+pub fn set_injection_jitter(
+    ms_min: u64,
+    ms_max: u64,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if ms_min > ms_max {
+        return Err(format!("Invalid jitter range: {ms_min} > {ms_max}"));
+    }
+
+    let data_dir = paths::data_dir(&app)?;
+
+    let jitter = InjectionJitter {
+        min_ms: ms_min,
+        max_ms: ms_max,
+    };
+    let mut inner = state.inner.lock().unwrap();
+    inner.settings.injection_jitter = jitter;
+    inner.jitter_tx.send_replace(jitter);
+    settings::save(&data_dir, &inner.settings)
+}
+
+/// The exact stanza the proxy would inject when switching to `mode`, based on
+/// the presence cached for the current session.
+#[tauri::command]
+pub fn preview_injection(mode: String, state: State<'_, AppState>) -> Result<String, String> {
+    let mode = parse_mode(&mode)?;
+    let last_presence = {
+        let inner = state.inner.lock().unwrap();
+        inner.presence_cache.latest().unwrap_or_default()
+    };
+
+    Ok(presence::injection_for_mode(&mode, &last_presence).unwrap_or_default())
+}
+
+/// Re-send the presence for the current mode on every live connection —
+/// unavailable while Offline, the cached presence while Online — exactly as
+/// a mode change would, without one. For when the server's view may have
+/// drifted, e.g. an injected unavailable lost in a network blip.
+#[tauri::command]
+pub fn reapply_presence(state: State<'_, AppState>) -> Result<(), String> {
+    let inner = state.inner.lock().unwrap();
+    let tx = inner.reapply_tx.as_ref().ok_or("Proxy is not running")?;
+    log::info!("Re-applying {:?} presence on request", inner.stealth_mode);
+    tx.send_replace(());
     Ok(())
 }
 
+/// What the proxy would do on a `from` → `to` toggle given the presence
+/// cached for the current session: the stanza it injects, if any, and whether
+/// that re-sends the cached presence.
+#[tauri::command]
+pub fn simulate_toggle(
+    from: String,
+    to: String,
+    state: State<'_, AppState>,
+) -> Result<presence::ToggleSimulation, String> {
+    let (from, to) = (parse_mode(&from)?, parse_mode(&to)?);
+    let last_presence = {
+        let inner = state.inner.lock().unwrap();
+        inner.presence_cache.latest().unwrap_or_default()
+    };
+
+    Ok(presence::simulate_toggle(&from, &to, &last_presence))
+}
+
+/// The last presence forwarded to the chat server (after filtering, or
+/// injected on a toggle) for `account`, or for any account if omitted.
+/// Tells "we sent unavailable but the server ignored it" apart from "we never
+/// sent it".
+#[tauri::command]
+pub fn get_last_sent_presence(
+    account: Option<String>,
+    state: State<'_, AppState>,
+) -> Option<String> {
+    let inner = state.inner.lock().unwrap();
+    inner.presence_cache.last_sent(account.as_deref())
+}
+
+/// Mode each chat connection was accepted in and every mode it was switched
+/// to afterwards, oldest first. With `get_last_sent_presence`, answers "was I
+/// actually invisible when I connected?".
+#[tauri::command]
+pub fn get_connection_mode_log(state: State<'_, AppState>) -> Vec<ConnectionModeEvent> {
+    let inner = state.inner.lock().unwrap();
+    inner.connections.mode_log()
+}
+
+/// Start or stop dumping the decrypted XMPP traffic (both directions, with
+/// timestamps) to the file `path` in the data dir; defaults to `capture.log`.
+/// Captures contain auth tokens unless `redact` is left on.
+#[tauri::command]
+pub fn set_capture(
+    enabled: bool,
+    path: Option<String>,
+    redact: Option<bool>,
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let inner = state.inner.lock().unwrap();
+    if !enabled {
+        inner.capture.stop();
+        log::info!("Traffic capture stopped");
+        return Ok(());
+    }
+
+    let data_dir = paths::data_dir(&app)?;
+    let path = paths::file_in(&data_dir, path.as_deref().unwrap_or("capture.log"))?;
+    let redact = redact.unwrap_or(true);
+    inner.capture.start(&path, redact)?;
+
+    if redact {
+        log::info!("Traffic capture started: {:?} (tokens redacted)", path);
+    } else {
+        log::warn!("Traffic capture started: {:?} — contains auth tokens, do not share", path);
+    }
+    Ok(())
+}
+
+/// Change the log level (`error`, `warn`, `info`, `debug`, `trace`, `off`)
+/// for the running app.
+#[tauri::command]
+pub fn set_log_level(level: String) -> Result<(), String> {
+    logging::set_level(&level)
+}
+
 #[derive(serde::Serialize)]
 pub struct CertStatus {
     pub ca_generated: bool,
@@ -245,6 +1456,143 @@ pub struct CertStatus {
     pub ca_trusted: bool,
 }
 
+/// State read by `launch_game` in a single lock acquisition.
+struct LaunchSnapshot {
+    initial_mode: StealthMode,
+    chat_host: String,
+    upstream_proxy: Option<String>,
+    config_timeout: Duration,
+    presence_cache: PresenceCache,
+    connections: ConnectionCounter,
+    visibility_rx: watch::Receiver<VisibilityRules>,
+    jitter_rx: watch::Receiver<InjectionJitter>,
+    account_modes_rx: watch::Receiver<AccountModes>,
+    fallback_hosts: Vec<String>,
+    capture: TrafficCapture,
+    offline_presence: OfflinePresence,
+    persist_presence: bool,
+    verify_unavailable_ack: bool,
+    strict_offline: bool,
+    config_proxy_port: Option<u16>,
+    upstream_config_url: String,
+    upstream_keepalive: Option<Duration>,
+    allow_arbitrary_host: bool,
+    config_headers_rx: watch::Receiver<Vec<String>>,
+}
+
+impl LaunchSnapshot {
+    fn take(inner: &AppStateInner) -> Self {
+        Self {
+            initial_mode: inner.stealth_mode.clone(),
+            chat_host: resolved_chat_host(inner),
+            upstream_proxy: inner.upstream_proxy.clone(),
+            config_timeout: Duration::from_secs(inner.settings.config_timeout_secs),
+            presence_cache: inner.presence_cache.clone(),
+            connections: inner.connections.clone(),
+            visibility_rx: inner.visibility_tx.subscribe(),
+            jitter_rx: inner.jitter_tx.subscribe(),
+            account_modes_rx: inner.account_modes_tx.subscribe(),
+            fallback_hosts: fallback_chat_hosts(inner),
+            capture: inner.capture.clone(),
+            offline_presence: inner.settings.offline_presence,
+            persist_presence: inner.settings.persist_presence,
+            verify_unavailable_ack: inner.settings.verify_unavailable_ack,
+            strict_offline: inner.settings.strict_offline,
+            config_proxy_port: inner.settings.config_proxy_port,
+            upstream_config_url: upstream_config_url(inner),
+            upstream_keepalive: inner.settings.upstream_keepalive_secs.map(Duration::from_secs),
+            allow_arbitrary_host: inner.settings.allow_arbitrary_chat_host,
+            config_headers_rx: inner.config_headers_tx.subscribe(),
+        }
+    }
+}
+
+/// Selected region's chat host, or the default before one is known.
+fn resolved_chat_host(inner: &AppStateInner) -> String {
+    inner
+        .detected_chat_host
+        .clone()
+        .unwrap_or_else(|| "na2.chat.si.riotgames.com".to_string())
+}
+
+/// Config server the next launch proxies to.
+fn upstream_config_url(inner: &AppStateInner) -> String {
+    config_proxy::resolve_upstream_config_url(
+        std::env::var(config_proxy::CONFIG_URL_ENV).ok(),
+        inner.settings.upstream_config_url.as_deref(),
+    )
+}
+
+/// Chat hosts tried when the target is unreachable: the user's list, or the
+/// selected region's.
+fn fallback_chat_hosts(inner: &AppStateInner) -> Vec<String> {
+    if inner.settings.chat_host_candidates.is_empty() {
+        inner.regions.fallback_chat_hosts(inner.detected_region.as_deref())
+    } else {
+        inner.settings.chat_host_candidates.clone()
+    }
+}
+
+/// Contents of the file written by `export_diagnostics_file`.
+#[derive(serde::Serialize)]
+pub struct DiagnosticsExport {
+    pub version: &'static str,
+    pub os: &'static str,
+    /// Unix time in seconds.
+    pub exported_at: u64,
+    pub config: EffectiveConfig,
+    pub status: StatusInfo,
+    pub connection_mode_log: Vec<ConnectionModeEvent>,
+    pub last_config: Option<ConfigDump>,
+    pub last_sent_presence: Option<String>,
+    /// Recent decrypted traffic, redacted. Empty unless a capture runs.
+    pub recent_traffic: Vec<CapturedStream>,
+}
+
+/// Outcome of `get_effective_config`.
+#[derive(serde::Serialize)]
+pub struct EffectiveConfig {
+    pub data_dir: String,
+    pub stealth_mode: StealthMode,
+    pub region: Option<String>,
+    /// Chat host a launch starts with.
+    pub chat_host: String,
+    /// Chat host the running XMPP proxy targets, once Riot's config named one.
+    pub live_chat_host: Option<String>,
+    pub fallback_chat_hosts: Vec<String>,
+    pub xmpp_listen_addr: Option<String>,
+    pub config_proxy_port: Option<u16>,
+    /// Riot config server requests are proxied to.
+    pub upstream_config_url: String,
+    /// Upstream proxy URL with credentials redacted.
+    pub upstream_proxy: Option<String>,
+    /// Trust roots used for upstream chat TLS, once the proxy has started.
+    pub upstream_roots: Option<xmpp_proxy::UpstreamRoots>,
+    pub visibility_rules: VisibilityRules,
+    pub settings: Settings,
+    pub certs: CertStatus,
+}
+
+/// Outcome of `first_run_setup`.
+#[derive(serde::Serialize)]
+pub struct FirstRunReport {
+    pub certs_ready: bool,
+    pub ca_trusted: bool,
+    /// Whether the CA was installed automatically, and how that went.
+    pub ca_install: CaInstallOutcome,
+    pub region: Option<String>,
+    /// Steps the user still has to do, in order.
+    pub needs_action: Vec<String>,
+}
+
+/// Outcome of `refresh_chat_host`.
+#[derive(serde::Serialize)]
+pub struct ChatHostRefresh {
+    pub previous: String,
+    pub current: String,
+    pub changed: bool,
+}
+
 #[derive(serde::Serialize)]
 pub struct RegionInfo {
     pub code: String,
@@ -1,53 +1,129 @@
+mod cli;
 mod commands;
-mod proxy;
+mod logging;
+mod paths;
+pub mod proxy;
 mod riot;
-mod state;
+mod settings;
+pub mod state;
 
 use state::AppState;
 use tauri::image::Image;
 use tauri::menu::{Menu, MenuItem};
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
-        .format_timestamp_secs()
-        .init();
+    logging::init();
 
     log::info!("Where Is Teemo starting");
 
-    let app_state = AppState::default();
+    let mut app_state = AppState::default();
+    if let Some(mode) = cli::mode_from_args(std::env::args().skip(1)) {
+        log::info!("Starting in {mode:?} mode (--mode)");
+        app_state.inner.get_mut().unwrap().set_stealth_mode(mode);
+    }
 
     tauri::Builder::default()
+        // Registered first so a second launch hands over before anything starts
+        .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
+            handle_launch(app, argv.into_iter().skip(1).collect());
+        }))
         .plugin(tauri_plugin_opener::init())
         .manage(app_state)
         .invoke_handler(tauri::generate_handler![
             commands::get_status,
             commands::set_stealth_mode,
+            commands::pause_stealth,
             commands::launch_game,
+            commands::launch_game_direct,
+            commands::refresh_chat_host,
             commands::stop_proxy,
+            commands::disconnect_chat,
+            commands::reconnect_chat,
+            commands::reset_state,
+            commands::get_proxy_ready,
             commands::get_cert_status,
             commands::install_ca,
+            commands::first_run_setup,
+            commands::set_auto_install_ca,
             commands::get_regions,
             commands::set_region,
+            commands::set_upstream_proxy,
+            commands::set_visibility_rules,
+            commands::get_settings,
+            commands::get_effective_config,
+            commands::get_last_config,
+            commands::set_upstream_config_url,
+            commands::set_close_to_tray,
+            commands::set_injection_jitter,
+            commands::get_config_headers,
+            commands::set_config_headers,
+            commands::preview_injection,
+            commands::simulate_toggle,
+            commands::reapply_presence,
+            commands::get_riot_lockfile,
+            commands::get_riot_accounts,
+            commands::set_chat_host_candidates,
+            commands::set_allow_arbitrary_chat_host,
+            commands::set_log_level,
+            commands::reload_regions,
+            commands::get_last_sent_presence,
+            commands::get_connection_mode_log,
+            commands::export_diagnostics_file,
+            commands::start_test_mode,
+            commands::set_capture,
+            commands::set_config_timeout,
+            commands::set_offline_presence,
+            commands::set_persist_presence,
+            commands::set_verify_unavailable_ack,
+            commands::set_strict_offline,
+            commands::set_config_proxy_port,
+            commands::set_upstream_keepalive,
+            commands::set_show_window_on_start,
+            commands::set_ca_trust_check,
         ])
         .setup(|app| {
-            let data_dir = app.path().app_data_dir()?;
+            let data_dir = paths::data_dir(app.handle())?;
+            load_settings(app, &data_dir);
             setup_certs(&data_dir);
-            setup_tray(app)?;
+            // Some Linux desktops have no tray; run with just the window there
+            let tray_available = match setup_tray(app) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("Failed to create the tray icon: {e} — running without it");
+                    false
+                }
+            };
+            app.state::<AppState>().inner.lock().unwrap().tray_available = tray_available;
+            show_initial_window(app, &data_dir);
+            spawn_ca_trust_check(app, data_dir);
             #[cfg(target_os = "macos")]
-            setup_click_outside_handler(app);
+            if tray_available {
+                setup_click_outside_handler(app);
+            }
             Ok(())
         })
         .on_window_event(|window, event| match event {
             tauri::WindowEvent::CloseRequested { api, .. } => {
-                api.prevent_close();
-                let _ = window.hide();
+                let close_to_tray = {
+                    let state = window.state::<AppState>();
+                    let inner = state.inner.lock().unwrap();
+                    inner.settings.close_to_tray && inner.tray_available
+                };
+                if close_to_tray {
+                    api.prevent_close();
+                    let _ = window.hide();
+                } else {
+                    quit_app(window.app_handle());
+                }
             }
             tauri::WindowEvent::ThemeChanged(theme) => {
-                if let Some(tray) = window.app_handle().tray_by_id("main-tray") {
-                    let _ = tray.set_icon(Some(tray_icon_for_theme(*theme)));
+                let app = window.app_handle();
+                if let Some(tray) = app.tray_by_id("main-tray") {
+                    let icon = tray_icon_for_theme(*theme, app.default_window_icon());
+                    let _ = tray.set_icon(Some(icon));
                 }
             }
             _ => {}
@@ -56,6 +132,49 @@ pub fn run() {
         .expect("error while running tauri application");
 }
 
+fn load_settings(app: &tauri::App, data_dir: &std::path::Path) {
+    let settings = settings::load(data_dir);
+    let state = app.state::<AppState>();
+    let mut inner = state.inner.lock().unwrap();
+    inner.jitter_tx.send_replace(settings.injection_jitter);
+    if let Some(headers) = &settings.config_headers {
+        inner.config_headers_tx.send_replace(headers.clone());
+    }
+    inner.settings = settings;
+    inner.regions = riot::config::load_regions(data_dir);
+}
+
+/// Serve a later launch from this instance: `--toggle` flips stealth like the
+/// tray (handy for hotkeys and launcher scripts), going Offline from any
+/// visible mode and back to the last visible one, `--mode` sets it, and a
+/// plain launch brings the window up. Without a proxy running the mode is
+/// only stored, and applies on the next Launch.
+fn handle_launch(handle: &tauri::AppHandle, args: Vec<String>) {
+    let app_state = handle.state::<AppState>();
+    let mode = match cli::launch_request(args) {
+        cli::LaunchRequest::Show => {
+            if let Some(window) = handle.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+            return;
+        }
+        cli::LaunchRequest::SetMode(mode) => mode,
+        cli::LaunchRequest::Toggle => {
+            let inner = app_state.inner.lock().unwrap();
+            if inner.stealth_mode.is_hidden() {
+                inner.last_visible_mode.clone()
+            } else {
+                state::StealthMode::Offline
+            }
+        }
+    };
+    log::info!("Stealth mode: {mode:?} (via another launch)");
+    let mut inner = app_state.inner.lock().unwrap();
+    commands::apply_stealth_mode(&mut inner, mode);
+    let _ = handle.emit("status-changed", inner.status());
+}
+
 fn setup_certs(data_dir: &std::path::Path) {
     match proxy::certs::ensure_ca(data_dir) {
         Ok(ca) => {
@@ -70,15 +189,100 @@ fn setup_certs(data_dir: &std::path::Path) {
     }
 }
 
-fn tray_icon_for_theme(theme: tauri::Theme) -> Image<'static> {
-    match theme {
-        tauri::Theme::Dark => {
-            Image::from_bytes(include_bytes!("../icons/icon-colored-white.png")).unwrap()
+/// Open the window while onboarding isn't finished (the first run), when
+/// the user asked for it at startup, or when there is no tray to start in.
+fn show_initial_window(app: &tauri::App, data_dir: &std::path::Path) {
+    let show = {
+        let state = app.state::<AppState>();
+        let mut inner = state.inner.lock().unwrap();
+        if inner.settings.first_run && commands::onboarding_complete(data_dir) {
+            commands::finish_first_run(&mut inner, data_dir);
+        }
+        inner.settings.first_run || inner.settings.show_window_on_start || !inner.tray_available
+    };
+
+    if show {
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
         }
-        _ => Image::from_bytes(include_bytes!("../icons/icon-colored-black.png")).unwrap(),
     }
 }
 
+/// Re-verify CA trust every `CA_TRUST_CHECK_INTERVAL` and emit
+/// `ca-trust-lost` when it disappears, so the user can reinstall it before
+/// the next launch silently breaks. Off with `settings.ca_trust_check`.
+fn spawn_ca_trust_check(app: &tauri::App, data_dir: std::path::PathBuf) {
+    let enabled = {
+        let state = app.state::<AppState>();
+        let inner = state.inner.lock().unwrap();
+        inner.settings.ca_trust_check
+    };
+    if !enabled {
+        return;
+    }
+
+    let handle = app.handle().clone();
+    tauri::async_runtime::spawn(async move {
+        let mut watch = proxy::certs::CaTrustWatch::default();
+        let mut interval = tokio::time::interval(proxy::certs::CA_TRUST_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let dir = data_dir.clone();
+            // Spawns `security`/`certutil`, so keep it off the async workers
+            let Ok(trusted) =
+                tokio::task::spawn_blocking(move || proxy::certs::is_ca_installed(&dir)).await
+            else {
+                continue;
+            };
+            if watch.observe(trusted) {
+                log::warn!("CA certificate is no longer trusted — it was removed from the store");
+                let _ = handle.emit("ca-trust-lost", ());
+            }
+        }
+    });
+}
+
+/// Stop the proxies and exit. Shared by the tray "Quit" item and the window
+/// close button when close-to-tray is disabled.
+fn quit_app(app: &tauri::AppHandle) {
+    log::info!("Quit requested — cleaning up");
+    let state = app.state::<AppState>();
+    let mut inner = state.inner.lock().unwrap();
+    state::teardown_proxy(&mut inner);
+    drop(inner);
+    app.exit(0);
+}
+
+fn tray_icon_for_theme(theme: tauri::Theme, window_icon: Option<&Image<'_>>) -> Image<'static> {
+    let bytes: &[u8] = match theme {
+        tauri::Theme::Dark => include_bytes!("../icons/icon-colored-white.png"),
+        _ => include_bytes!("../icons/icon-colored-black.png"),
+    };
+    resolve_icon(bytes, window_icon)
+}
+
+/// Decode a bundled icon, falling back to the app's window icon and then to
+/// a placeholder, so a bad or missing icon can never stop the tray.
+fn resolve_icon(bytes: &[u8], window_icon: Option<&Image<'_>>) -> Image<'static> {
+    match Image::from_bytes(bytes) {
+        Ok(icon) => icon,
+        Err(e) => {
+            log::warn!("Failed to decode the tray icon: {e} — using a fallback");
+            window_icon
+                .map(|icon| icon.clone().to_owned())
+                .unwrap_or_else(placeholder_icon)
+        }
+    }
+}
+
+/// Plain grey square, for when no icon at all is available.
+fn placeholder_icon() -> Image<'static> {
+    const SIZE: u32 = 32;
+    let rgba = [0x80, 0x80, 0x80, 0xff].repeat((SIZE * SIZE) as usize);
+    Image::new_owned(rgba, SIZE, SIZE)
+}
+
 #[cfg(target_os = "macos")]
 fn setup_click_outside_handler(app: &tauri::App) {
     use block2::RcBlock;
@@ -109,6 +313,9 @@ fn setup_click_outside_handler(app: &tauri::App) {
 fn setup_tray(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     let offline_item = MenuItem::with_id(app, "offline", "Invisible", true, None::<&str>)?;
     let online_item = MenuItem::with_id(app, "online", "Online", true, None::<&str>)?;
+    let away_item = MenuItem::with_id(app, "away", "Away", true, None::<&str>)?;
+    let dnd_item = MenuItem::with_id(app, "dnd", "Do Not Disturb", true, None::<&str>)?;
+    let xa_item = MenuItem::with_id(app, "xa", "Extended Away", true, None::<&str>)?;
     let separator = tauri::menu::PredefinedMenuItem::separator(app)?;
     let show_item = MenuItem::with_id(app, "show", "Show Window", true, None::<&str>)?;
     let quit_item = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
@@ -118,6 +325,9 @@ fn setup_tray(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
         &[
             &offline_item,
             &online_item,
+            &away_item,
+            &dnd_item,
+            &xa_item,
             &separator,
             &show_item,
             &quit_item,
@@ -130,48 +340,27 @@ fn setup_tray(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or(tauri::Theme::Dark);
 
     TrayIconBuilder::with_id("main-tray")
-        .icon(tray_icon_for_theme(theme))
+        .icon(tray_icon_for_theme(theme, app.default_window_icon()))
         .tooltip("Where Is Teemo")
         .menu(&menu)
         .show_menu_on_left_click(false)
         .on_menu_event(|app, event| match event.id.as_ref() {
-            "offline" => {
-                let state = app.state::<AppState>();
-                let mut inner = state.inner.lock().unwrap();
-                inner.stealth_mode = state::StealthMode::Offline;
-                if let Some(tx) = &inner.mode_tx {
-                    let _ = tx.send(state::StealthMode::Offline);
-                }
-                log::info!("Stealth mode: Invisible (via tray)");
-            }
-            "online" => {
-                let state = app.state::<AppState>();
-                let mut inner = state.inner.lock().unwrap();
-                inner.stealth_mode = state::StealthMode::Online;
-                if let Some(tx) = &inner.mode_tx {
-                    let _ = tx.send(state::StealthMode::Online);
+            id @ ("offline" | "online" | "away" | "dnd" | "xa") => match commands::parse_mode(id) {
+                Ok(mode) => {
+                    log::info!("Stealth mode: {mode:?} (via tray)");
+                    let state = app.state::<AppState>();
+                    let mut inner = state.inner.lock().unwrap();
+                    commands::apply_stealth_mode(&mut inner, mode);
                 }
-                log::info!("Stealth mode: Online (via tray)");
-            }
+                Err(e) => log::error!("Tray: {e}"),
+            },
             "show" => {
                 if let Some(window) = app.get_webview_window("main") {
                     let _ = window.show();
                     let _ = window.set_focus();
                 }
             }
-            "quit" => {
-                log::info!("Quit requested — cleaning up");
-                let state = app.state::<AppState>();
-                let mut inner = state.inner.lock().unwrap();
-                if let Some(tx) = inner.shutdown_tx.take() {
-                    let _ = tx.send(true);
-                }
-                if let Some(tx) = inner.config_shutdown_tx.take() {
-                    let _ = tx.send(true);
-                }
-                drop(inner);
-                app.exit(0);
-            }
+            "quit" => quit_app(app),
             _ => {}
         })
         .on_tray_icon_event(|tray, event| {
@@ -290,3 +479,30 @@ fn smart_position_windows<R: tauri::Runtime>(
 
     Some((x, y))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundled_tray_icons_decode() {
+        let icon = tray_icon_for_theme(tauri::Theme::Dark, None);
+        assert!(icon.width() > 0 && icon.height() > 0);
+        let icon = tray_icon_for_theme(tauri::Theme::Light, None);
+        assert!(icon.width() > 0 && icon.height() > 0);
+    }
+
+    #[test]
+    fn test_icon_falls_back_to_window_icon() {
+        let window_icon = Image::new_owned(vec![0; 4 * 4 * 4], 4, 4);
+        let icon = resolve_icon(b"not a png", Some(&window_icon));
+        assert_eq!((icon.width(), icon.height()), (4, 4));
+    }
+
+    #[test]
+    fn test_icon_falls_back_to_placeholder() {
+        let icon = resolve_icon(b"not a png", None);
+        assert_eq!((icon.width(), icon.height()), (32, 32));
+        assert_eq!(icon.rgba().len(), 32 * 32 * 4);
+    }
+}
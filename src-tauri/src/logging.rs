@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use log::LevelFilter;
+
+/// Env var that switches log output to one JSON object per line.
+const LOG_FORMAT_ENV: &str = "WHERE_IS_TEEMO_LOG_FORMAT";
+
+/// Level used when `RUST_LOG` isn't set.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// Initialize env_logger. Human-readable by default; set
+/// `WHERE_IS_TEEMO_LOG_FORMAT=json` for machine-parseable JSON lines.
+///
+/// Without `RUST_LOG`, env_logger itself lets everything through and the
+/// active level is the global max level, so `set_level` can raise or lower it
+/// at runtime. With `RUST_LOG`, its directives still apply on top.
+pub fn init() {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default());
+    let from_env = std::env::var_os("RUST_LOG").is_some();
+    if !from_env {
+        builder.filter_level(LevelFilter::Trace);
+    }
+
+    if json_format_requested() {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp_secs().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    } else {
+        builder.format_timestamp_secs();
+    }
+
+    let logger = builder.build();
+    let max_level = if from_env { logger.filter() } else { DEFAULT_LEVEL };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Change the active log level without restarting.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_level(level)?;
+    log::set_max_level(filter);
+    // Logged after the change so raising the level is visible immediately
+    log::info!("Log level set to {filter}");
+    Ok(())
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level
+        .trim()
+        .parse()
+        .map_err(|_| format!("Unknown log level: {level}"))
+}
+
+fn json_format_requested() -> bool {
+    std::env::var(LOG_FORMAT_ENV).is_ok_and(|v| v.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("debug"), Ok(LevelFilter::Debug));
+        assert_eq!(parse_level("WARN"), Ok(LevelFilter::Warn));
+        assert_eq!(parse_level("off"), Ok(LevelFilter::Off));
+    }
+
+    #[test]
+    fn test_parse_level_unknown() {
+        assert!(parse_level("verbose").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use tauri::{AppHandle, Manager};
+
+/// Overrides the data directory (certs, settings), for portable installs and testing.
+pub const DATA_DIR_ENV: &str = "WHERE_IS_TEEMO_DATA_DIR";
+
+/// Resolved on the first call (from `setup`) and reused after that.
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Directory holding certs and settings. Uses `WHERE_IS_TEEMO_DATA_DIR` when
+/// it points to a writable directory, otherwise the app data dir.
+pub fn data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    if let Some(dir) = DATA_DIR.get() {
+        return Ok(dir.clone());
+    }
+    let dir = match override_dir(std::env::var_os(DATA_DIR_ENV)) {
+        Some(dir) => dir,
+        None => app
+            .path()
+            .app_data_dir()
+            .map_err(|e| format!("Failed to get app data dir: {e}"))?,
+    };
+    Ok(DATA_DIR.get_or_init(|| dir).clone())
+}
+
+fn override_dir(value: Option<OsString>) -> Option<PathBuf> {
+    let dir = PathBuf::from(value.filter(|v| !v.is_empty())?);
+    match ensure_writable(&dir) {
+        Ok(()) => Some(dir),
+        Err(e) => {
+            log::warn!("Ignoring {DATA_DIR_ENV}={:?}: {e} — using app data dir", dir);
+            None
+        }
+    }
+}
+
+fn ensure_writable(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create directory: {e}"))?;
+    let probe = dir.join(".write-test");
+    fs::write(&probe, b"").map_err(|e| format!("Directory is not writable: {e}"))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// `name` inside `dir`, when `name` is a bare file name. Absolute paths and
+/// anything with a directory part (including `..`) are refused, so a file
+/// named by the UI can't land outside `dir`.
+pub fn file_in(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file)), None) => Ok(dir.join(file)),
+        _ => Err(format!("Not a plain file name: {name:?}")),
+    }
+}
+
+/// Replace `path` with `contents` in one step: write a temp file next to it,
+/// then rename it over. Readers never see a partially written file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let Some(name) = path.file_name() else {
+        return Err(format!("Not a file path: {:?}", path));
+    };
+    let mut tmp_name = name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    fs::write(&tmp, contents).map_err(|e| format!("Failed to write {:?}: {e}", tmp))?;
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("Failed to replace {:?}: {e}", path)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wit-paths-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_override_dir_writable() {
+        let dir = temp_path("writable");
+        assert_eq!(override_dir(Some(dir.clone().into_os_string())), Some(dir.clone()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_file_in_accepts_bare_names_only() {
+        let dir = Path::new("data");
+        assert_eq!(file_in(dir, "capture.log"), Ok(dir.join("capture.log")));
+        for name in ["", "..", "../capture.log", "logs/capture.log", "/tmp/capture.log", "."] {
+            assert!(file_in(dir, name).is_err(), "{name:?} should be refused");
+        }
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = temp_path("atomic");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("diagnostics.json");
+        fs::write(&path, b"old report").unwrap();
+
+        write_atomic(&path, b"{}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{}");
+        assert!(!dir.join("diagnostics.json.tmp").exists());
+
+        assert!(write_atomic(&dir.join("missing").join("x.json"), b"{}").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_override_dir_unset_or_empty() {
+        assert_eq!(override_dir(None), None);
+        assert_eq!(override_dir(Some(OsString::new())), None);
+    }
+
+    #[test]
+    fn test_override_dir_invalid_falls_back() {
+        // A path below a regular file can never be created
+        let file = temp_path("file");
+        fs::write(&file, b"").unwrap();
+        assert_eq!(override_dir(Some(file.join("data").into_os_string())), None);
+        let _ = fs::remove_file(&file);
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which hop of the proxy a captured chunk was seen on.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    /// Received from the Riot chat server, forwarded as-is to the client.
+    ServerToClient,
+    /// Received from the Riot client, before filtering.
+    ClientToProxy,
+    /// Sent to the Riot chat server, after filtering or injected.
+    ProxyToServer,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::ServerToClient => "S→C",
+            Direction::ClientToProxy => "C→P",
+            Direction::ProxyToServer => "P→S",
+        }
+    }
+}
+
+/// Bytes of recent traffic kept in memory while a capture runs, for
+/// diagnostics exports. The oldest chunks go first.
+const RECENT_BYTES: usize = 64 * 1024;
+
+/// Opt-in dump of the decrypted XMPP traffic, shared by every connection.
+/// Unredacted captures contain auth and entitlement tokens. While a capture
+/// runs the last `RECENT_BYTES` of traffic are also kept in memory, as read,
+/// and only redacted when `recent` reassembles them.
+#[derive(Clone, Default)]
+pub struct TrafficCapture {
+    sink: Arc<Mutex<Option<CaptureSink>>>,
+    /// Connection this handle records for; None for the shared handle.
+    conn: Option<u64>,
+}
+
+struct CaptureSink {
+    file: File,
+    redact: bool,
+    recent: VecDeque<RawChunk>,
+    recent_bytes: usize,
+}
+
+struct RawChunk {
+    conn: Option<u64>,
+    direction: Direction,
+    data: String,
+}
+
+/// One connection's traffic in one direction, reassembled from the recent
+/// chunks and redacted as a whole.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CapturedStream {
+    pub conn: Option<u64>,
+    pub direction: &'static str,
+    pub data: String,
+}
+
+impl TrafficCapture {
+    /// A handle recording `id`'s traffic into the same capture, so the
+    /// chunks of concurrent connections can be told apart.
+    pub fn for_connection(&self, id: u64) -> Self {
+        Self {
+            sink: self.sink.clone(),
+            conn: Some(id),
+        }
+    }
+
+    /// Start appending traffic to `path`, replacing any capture in progress.
+    pub fn start(&self, path: &Path, redact: bool) -> Result<(), String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open capture file {:?}: {e}", path))?;
+        *self.sink.lock().unwrap() = Some(CaptureSink {
+            file,
+            redact,
+            recent: VecDeque::new(),
+            recent_bytes: 0,
+        });
+        Ok(())
+    }
+
+    /// Stop the capture and forget the traffic kept in memory.
+    pub fn stop(&self) {
+        *self.sink.lock().unwrap() = None;
+    }
+
+    /// The traffic kept in memory, one stream per connection and direction in
+    /// the order they first appear, redacted. Empty unless a capture runs.
+    pub fn recent(&self) -> Vec<CapturedStream> {
+        let sink = self.sink.lock().unwrap();
+        let Some(capture) = sink.as_ref() else {
+            return Vec::new();
+        };
+        let mut streams: Vec<CapturedStream> = Vec::new();
+        for chunk in &capture.recent {
+            let direction = chunk.direction.label();
+            match streams
+                .iter_mut()
+                .find(|s| s.conn == chunk.conn && s.direction == direction)
+            {
+                Some(stream) => stream.data.push_str(&chunk.data),
+                None => streams.push(CapturedStream {
+                    conn: chunk.conn,
+                    direction,
+                    data: chunk.data.clone(),
+                }),
+            }
+        }
+        for stream in &mut streams {
+            stream.data = redact(&stream.data);
+        }
+        streams
+    }
+
+    /// Append one chunk with a timestamp and direction marker. A failed write
+    /// stops the capture rather than the connection.
+    pub fn record(&self, direction: Direction, data: &str) {
+        let mut sink = self.sink.lock().unwrap();
+        let Some(capture) = sink.as_mut() else {
+            return;
+        };
+
+        capture.remember(RawChunk {
+            conn: self.conn,
+            direction,
+            data: data.to_string(),
+        });
+        let data = if capture.redact {
+            redact(data)
+        } else {
+            data.to_string()
+        };
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+
+        let line = format!("{millis} {} {} {data}\n", direction.label(), data.len());
+        if let Err(e) = capture.file.write_all(line.as_bytes()) {
+            log::error!("Traffic capture write failed: {e} — capture stopped");
+            *sink = None;
+        }
+    }
+}
+
+impl CaptureSink {
+    fn remember(&mut self, chunk: RawChunk) {
+        self.recent_bytes += chunk.data.len();
+        self.recent.push_back(chunk);
+        while self.recent_bytes > RECENT_BYTES && self.recent.len() > 1 {
+            if let Some(old) = self.recent.pop_front() {
+                self.recent_bytes -= old.data.len();
+            }
+        }
+    }
+}
+
+/// Elements whose text holds credentials (SASL auth, entitlement tokens).
+const SECRET_ELEMENTS: &[&str] = &["auth", "token", "rso_token", "pas_token"];
+
+/// Replace the contents of credential-bearing elements with `[REDACTED]`.
+pub fn redact(data: &str) -> String {
+    let mut out = data.to_string();
+    for name in SECRET_ELEMENTS {
+        out = redact_element(&out, name);
+    }
+    out
+}
+
+fn redact_element(data: &str, name: &str) -> String {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut out = String::with_capacity(data.len());
+    let mut rest = data;
+
+    // Starts inside a secret whose opening tag came in an earlier chunk
+    if let Some(end) = rest.find(&close) {
+        if end > 0 && !rest[..end].contains(&open) {
+            out.push_str("[REDACTED]");
+            rest = &rest[end..];
+        }
+    }
+
+    while let Some(start) = rest.find(&open) {
+        // Only match the exact element name, not e.g. <authority>
+        let after_name = &rest[start + open.len()..];
+        if !after_name.starts_with(['>', ' ', '/', '\t', '\n']) {
+            out.push_str(&rest[..start + open.len()]);
+            rest = after_name;
+            continue;
+        }
+        let Some(tag_end) = after_name.find('>') else {
+            break;
+        };
+        let content_start = start + open.len() + tag_end + 1;
+        out.push_str(&rest[..content_start]);
+        rest = &rest[content_start..];
+
+        // Self-closing: nothing to hide
+        if after_name[..tag_end].ends_with('/') {
+            continue;
+        }
+        match rest.find(&close) {
+            Some(end) => {
+                out.push_str("[REDACTED]");
+                rest = &rest[end..];
+            }
+            None => {
+                // Secret continues into the next chunk; hide what we have
+                out.push_str("[REDACTED]");
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_auth() {
+        let data = r#"<auth xmlns="urn:ietf:params:xml:ns:xmpp-sasl" mechanism="X-Riot-RSO-PAS"><rso_token>abc</rso_token><pas_token>def</pas_token></auth>"#;
+        assert_eq!(
+            redact(data),
+            r#"<auth xmlns="urn:ietf:params:xml:ns:xmpp-sasl" mechanism="X-Riot-RSO-PAS">[REDACTED]</auth>"#
+        );
+    }
+
+    #[test]
+    fn test_redact_entitlements_token() {
+        let data = r#"<iq type="set"><entitlements><token xmlns="">jwt.value</token></entitlements></iq>"#;
+        assert_eq!(
+            redact(data),
+            r#"<iq type="set"><entitlements><token xmlns="">[REDACTED]</token></entitlements></iq>"#
+        );
+    }
+
+    #[test]
+    fn test_redact_leaves_presence_alone() {
+        let data = r#"<presence><show>chat</show><status>Ranked</status></presence>"#;
+        assert_eq!(redact(data), data);
+    }
+
+    #[test]
+    fn test_redact_split_secret() {
+        assert_eq!(redact("<auth>partial-tok"), "<auth>[REDACTED]");
+    }
+
+    #[test]
+    fn test_redact_secret_continued_from_earlier_chunk() {
+        assert_eq!(redact("tok-rest</token></iq>"), "[REDACTED]</token></iq>");
+        assert_eq!(redact("<b>x</b></token>"), "[REDACTED]</token>");
+    }
+
+    fn temp_capture(name: &str) -> (TrafficCapture, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("wit-capture-{}-{name}", std::process::id()));
+        let capture = TrafficCapture::default();
+        capture.start(&path, false).unwrap();
+        (capture, path)
+    }
+
+    #[test]
+    fn test_recent_is_empty_without_a_capture() {
+        let capture = TrafficCapture::default();
+        capture.record(Direction::ClientToProxy, "<presence/>");
+        assert!(capture.recent().is_empty());
+    }
+
+    #[test]
+    fn test_recent_redacts_secret_split_across_reads() {
+        let (capture, path) = temp_capture("split");
+        let (first, second) = (capture.for_connection(1), capture.for_connection(2));
+        first.record(Direction::ClientToProxy, "<auth>abc");
+        second.record(Direction::ClientToProxy, "<presence/>");
+        first.record(Direction::ServerToClient, "<success/>");
+        first.record(Direction::ClientToProxy, "def</auth>");
+
+        let recent = capture.recent();
+        assert_eq!(
+            recent,
+            vec![
+                CapturedStream {
+                    conn: Some(1),
+                    direction: "C→P",
+                    data: "<auth>[REDACTED]</auth>".to_string(),
+                },
+                CapturedStream {
+                    conn: Some(2),
+                    direction: "C→P",
+                    data: "<presence/>".to_string(),
+                },
+                CapturedStream {
+                    conn: Some(1),
+                    direction: "S→C",
+                    data: "<success/>".to_string(),
+                },
+            ]
+        );
+
+        capture.stop();
+        assert!(capture.recent().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_recent_drops_oldest_traffic() {
+        let (capture, path) = temp_capture("bounded");
+        let chunk = "x".repeat(RECENT_BYTES / 4);
+        for _ in 0..8 {
+            capture.record(Direction::ServerToClient, &chunk);
+        }
+        capture.record(Direction::ServerToClient, "<iq id='last'/>");
+        let data = &capture.recent()[0].data;
+        assert!(data.len() <= RECENT_BYTES);
+        assert!(data.ends_with("<iq id='last'/>"));
+        capture.stop();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -2,8 +2,18 @@ use rcgen::{
     BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
     KeyUsagePurpose,
 };
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::RootCertStore;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// A server cert expiring within this window is replaced rather than reused.
+const SERVER_CERT_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
 
 pub struct CaCert {
     pub cert_pem: String,
@@ -56,9 +66,27 @@ pub fn ensure_ca(app_data_dir: &Path) -> Result<CaCert, String> {
     fs::write(&cert_path, &ca.cert_pem).map_err(|e| format!("Failed to write CA cert: {e}"))?;
     fs::write(&key_path, &ca.key_pem).map_err(|e| format!("Failed to write CA key: {e}"))?;
 
+    // Any server cert left on disk was signed by the previous CA and no longer chains
+    if server_cert_path(app_data_dir).exists() {
+        log::info!("Removing server certificate issued by the previous CA");
+        let _ = fs::remove_file(server_cert_path(app_data_dir));
+        let _ = fs::remove_file(server_key_path(app_data_dir));
+    }
+
     Ok(ca)
 }
 
+/// Delete all generated certificates so the next `ensure_ca` starts fresh.
+/// A regenerated CA must be trusted again before the proxy works.
+pub fn remove_certs(app_data_dir: &Path) -> Result<(), String> {
+    let dir = certs_dir(app_data_dir);
+    if dir.exists() {
+        log::info!("Removing certificates from {:?}", dir);
+        fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove certs dir: {e}"))?;
+    }
+    Ok(())
+}
+
 fn generate_ca() -> Result<CaCert, String> {
     let mut params = CertificateParams::default();
     params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
@@ -82,11 +110,34 @@ fn generate_ca() -> Result<CaCert, String> {
     })
 }
 
-/// Generate a server certificate signed by the CA, for localhost proxy use.
+/// Load the server certificate from disk if it is still good for `ca`, or
+/// generate a new one signed by the CA, for localhost proxy use.
 pub fn generate_server_cert(ca: &CaCert, app_data_dir: &Path) -> Result<ServerCert, String> {
     let cert_path = server_cert_path(app_data_dir);
     let key_path = server_key_path(app_data_dir);
 
+    if let (Ok(cert_pem), Ok(key_pem)) =
+        (fs::read_to_string(&cert_path), fs::read_to_string(&key_path))
+    {
+        if is_reusable(&cert_pem, &key_pem, &ca.cert_pem) {
+            log::info!("Reusing existing server certificate");
+            return Ok(ServerCert { cert_pem, key_pem });
+        }
+        log::info!("Existing server certificate is stale — regenerating");
+    }
+
+    let server = sign_server_cert(ca)?;
+
+    fs::write(&cert_path, &server.cert_pem)
+        .map_err(|e| format!("Failed to write server cert: {e}"))?;
+    fs::write(&key_path, &server.key_pem)
+        .map_err(|e| format!("Failed to write server key: {e}"))?;
+
+    log::info!("Server certificate generated for 127.0.0.1/localhost");
+    Ok(server)
+}
+
+fn sign_server_cert(ca: &CaCert) -> Result<ServerCert, String> {
     // CertificateParams::new() auto-detects IP vs DNS SANs from strings
     let mut params = CertificateParams::new(vec![
         "127.0.0.1".to_string(),
@@ -114,18 +165,78 @@ pub fn generate_server_cert(ca: &CaCert, app_data_dir: &Path) -> Result<ServerCe
         .signed_by(&server_key, &issuer)
         .map_err(|e| format!("Failed to sign server cert: {e}"))?;
 
-    let server = ServerCert {
+    Ok(ServerCert {
         cert_pem: server_cert.pem(),
         key_pem: server_key.serialize_pem(),
+    })
+}
+
+/// Whether the on-disk server cert chains to the on-disk CA. False if either
+/// is missing, or if the CA was regenerated after the server cert was issued.
+pub fn server_cert_matches_ca(app_data_dir: &Path) -> bool {
+    let server = fs::read_to_string(server_cert_path(app_data_dir));
+    let ca = fs::read_to_string(ca_cert_path(app_data_dir));
+    match (server, ca) {
+        (Ok(server), Ok(ca)) => chains_to_ca(&server, &ca),
+        _ => false,
+    }
+}
+
+/// Whether an on-disk server cert can be used again: it chains to the CA now
+/// and for a while yet, and the key is the one it was issued for.
+fn is_reusable(server_cert_pem: &str, server_key_pem: &str, ca_cert_pem: &str) -> bool {
+    let renew_at = (SystemTime::now() + SERVER_CERT_RENEW_BEFORE)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    chains_to_ca(server_cert_pem, ca_cert_pem)
+        && chains_to_ca_at(server_cert_pem, ca_cert_pem, UnixTime::since_unix_epoch(renew_at))
+        && key_matches_cert(server_key_pem, server_cert_pem)
+}
+
+fn key_matches_cert(key_pem: &str, cert_pem: &str) -> bool {
+    let (Ok(key), Some(cert)) = (KeyPair::from_pem(key_pem), first_cert_der(cert_pem)) else {
+        return false;
+    };
+    let Ok((_, parsed)) = X509Certificate::from_der(cert.as_ref()) else {
+        return false;
     };
+    parsed.public_key().subject_public_key.data.as_ref() == key.public_key_raw()
+}
 
-    fs::write(&cert_path, &server.cert_pem)
-        .map_err(|e| format!("Failed to write server cert: {e}"))?;
-    fs::write(&key_path, &server.key_pem)
-        .map_err(|e| format!("Failed to write server key: {e}"))?;
+/// Verify `server_cert_pem` was signed by `ca_cert_pem` and is valid for localhost.
+fn chains_to_ca(server_cert_pem: &str, ca_cert_pem: &str) -> bool {
+    chains_to_ca_at(server_cert_pem, ca_cert_pem, UnixTime::now())
+}
 
-    log::info!("Server certificate generated for 127.0.0.1/localhost");
-    Ok(server)
+/// `chains_to_ca`, checking validity at `time` instead of now.
+fn chains_to_ca_at(server_cert_pem: &str, ca_cert_pem: &str, time: UnixTime) -> bool {
+    let (Some(server), Some(ca)) = (first_cert_der(server_cert_pem), first_cert_der(ca_cert_pem))
+    else {
+        return false;
+    };
+
+    let mut roots = RootCertStore::empty();
+    if roots.add(ca).is_err() {
+        return false;
+    }
+
+    let verifier = match WebPkiServerVerifier::builder(Arc::new(roots)).build() {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+
+    let Ok(name) = ServerName::try_from("localhost") else {
+        return false;
+    };
+
+    verifier
+        .verify_server_cert(&server, &[], &name, &[], time)
+        .is_ok()
+}
+
+fn first_cert_der(pem: &str) -> Option<CertificateDer<'static>> {
+    let mut reader = std::io::Cursor::new(pem);
+    rustls_pemfile::certs(&mut reader).next()?.ok()
 }
 
 /// Check if the CA is already installed in the system trust store.
@@ -154,14 +265,8 @@ pub fn is_ca_installed(app_data_dir: &Path) -> bool {
 
     #[cfg(target_os = "windows")]
     {
-        let output = std::process::Command::new("certutil")
-            .args(["-user", "-verifystore", "Root", "Where Is Teemo CA"])
-            .output();
-
-        match output {
-            Ok(o) => o.status.success(),
-            Err(_) => false,
-        }
+        // Either store counts; the machine store is the fallback install
+        is_in_windows_store(true) || is_in_windows_store(false)
     }
 
     #[cfg(not(any(target_os = "macos", target_os = "windows")))]
@@ -170,6 +275,49 @@ pub fn is_ca_installed(app_data_dir: &Path) -> bool {
     }
 }
 
+/// How often the background check re-verifies that the CA is still trusted.
+pub const CA_TRUST_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Follows `is_ca_installed` across periodic checks and reports the moment
+/// trust goes away — an OS update or security tool removing the CA — once
+/// per loss. A CA that was never trusted isn't a loss.
+#[derive(Debug, Default)]
+pub struct CaTrustWatch {
+    trusted: bool,
+}
+
+impl CaTrustWatch {
+    /// Record a check; true when it is the first to find trust gone.
+    pub fn observe(&mut self, trusted: bool) -> bool {
+        let lost = self.trusted && !trusted;
+        self.trusted = trusted;
+        lost
+    }
+}
+
+/// What onboarding did about trusting the CA.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaInstallOutcome {
+    /// The CA was already trusted; nothing to do.
+    AlreadyTrusted,
+    /// Not trusted, and automatic installation is off.
+    Skipped,
+    Installed,
+    /// The user declined the OS admin prompt.
+    Cancelled,
+    Failed,
+}
+
+/// Whether an `install_ca_system` error means the user dismissed the admin
+/// prompt rather than the install failing.
+pub fn install_was_cancelled(error: &str) -> bool {
+    // osascript reports a dismissed dialog as "User canceled. (-128)"
+    error.contains("User canceled")
+        || error.contains("(-128)")
+        || error.contains("Administrator permission was denied")
+}
+
 /// Install the CA certificate in the OS trust store.
 pub fn install_ca_system(app_data_dir: &Path) -> Result<(), String> {
     let cert_path = ca_cert_path(app_data_dir);
@@ -187,10 +335,7 @@ pub fn install_ca_system(app_data_dir: &Path) -> Result<(), String> {
     #[cfg(target_os = "macos")]
     {
         log::info!("Installing CA in macOS System Keychain (will prompt for admin)");
-        let script = format!(
-            r#"do shell script "security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain '{}'" with administrator privileges"#,
-            cert_path_str
-        );
+        let script = macos_install_script(cert_path_str);
         let output = std::process::Command::new("osascript")
             .args(["-e", &script])
             .output()
@@ -212,7 +357,14 @@ pub fn install_ca_system(app_data_dir: &Path) -> Result<(), String> {
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Failed to install CA: {stderr}"));
+            log::warn!("User store install failed: {stderr}");
+        }
+
+        // The machine store needs the UAC prompt, so it's only the fallback
+        // for when the user store install didn't make the CA trusted
+        if !is_ca_installed(app_data_dir) {
+            log::info!("CA still not trusted — installing it in the machine store");
+            install_windows_machine_store(cert_path_str)?;
         }
     }
 
@@ -224,3 +376,175 @@ pub fn install_ca_system(app_data_dir: &Path) -> Result<(), String> {
     log::info!("CA certificate installed successfully");
     Ok(())
 }
+
+/// AppleScript that adds the CA to the System Keychain with an admin prompt.
+/// The path is quoted for the shell command, and that whole command again
+/// for the AppleScript string, so quotes in the path can't break out.
+#[cfg(any(target_os = "macos", test))]
+fn macos_install_script(cert_path: &str) -> String {
+    let command = format!(
+        "security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain {}",
+        shell_quote(cert_path)
+    );
+    format!(
+        "do shell script {} with administrator privileges",
+        applescript_string(&command)
+    )
+}
+
+/// Single-quote for `sh`: a `'` inside becomes `'\''`.
+#[cfg(any(target_os = "macos", test))]
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Double-quoted AppleScript string literal.
+#[cfg(any(target_os = "macos", test))]
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', r"\\").replace('"', "\\\""))
+}
+
+/// Whether our CA is in the Windows Root store, per-user or machine-wide.
+#[cfg(target_os = "windows")]
+fn is_in_windows_store(user: bool) -> bool {
+    let mut args = vec!["-verifystore", "Root", "Where Is Teemo CA"];
+    if user {
+        args.insert(0, "-user");
+    }
+
+    match std::process::Command::new("certutil").args(&args).output() {
+        Ok(o) => o.status.success(),
+        Err(_) => false,
+    }
+}
+
+/// Add the CA to the machine Root store. Needs admin rights, so certutil is
+/// started elevated and Windows shows a UAC prompt.
+#[cfg(target_os = "windows")]
+fn install_windows_machine_store(cert_path: &str) -> Result<(), String> {
+    let quoted = cert_path.replace('\'', "''");
+    let script = format!(
+        "$p = Start-Process certutil -ArgumentList '-addstore','Root','\"{quoted}\"' \
+         -Verb RunAs -Wait -PassThru -WindowStyle Hidden; exit $p.ExitCode"
+    );
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .map_err(|e| format!("Failed to run powershell: {e}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Start-Process fails this way when the UAC prompt is declined
+        if stderr.contains("canceled by the user") {
+            return Err(
+                "Administrator permission was denied — the CA could not be installed \
+                 machine-wide"
+                    .to_string(),
+            );
+        }
+        return Err(format!("Failed to install CA in the machine store: {stderr}"));
+    }
+
+    if !is_in_windows_store(false) {
+        return Err("CA install in the machine store did not take effect".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_cancellation_detected() {
+        assert!(install_was_cancelled(
+            "Failed to install CA: 0:170: execution error: User canceled. (-128)"
+        ));
+        assert!(install_was_cancelled(
+            "Administrator permission was denied — the CA could not be installed machine-wide"
+        ));
+        assert!(!install_was_cancelled("Failed to run osascript: not found"));
+        assert!(!install_was_cancelled("CA install in the machine store did not take effect"));
+    }
+
+    #[test]
+    fn test_ca_trust_loss_reported_once() {
+        let mut watch = CaTrustWatch::default();
+        assert!(!watch.observe(false), "never trusted isn't a loss");
+        assert!(!watch.observe(true));
+        assert!(watch.observe(false));
+        assert!(!watch.observe(false));
+        assert!(!watch.observe(true));
+        assert!(watch.observe(false), "lost again after reinstalling");
+    }
+
+    #[test]
+    fn test_server_cert_chains_to_its_ca() {
+        let ca = generate_ca().unwrap();
+        let server = sign_server_cert(&ca).unwrap();
+        assert!(chains_to_ca(&server.cert_pem, &ca.cert_pem));
+    }
+
+    #[test]
+    fn test_server_cert_rejected_by_regenerated_ca() {
+        let old_ca = generate_ca().unwrap();
+        let server = sign_server_cert(&old_ca).unwrap();
+        let new_ca = generate_ca().unwrap();
+        assert!(!chains_to_ca(&server.cert_pem, &new_ca.cert_pem));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wit-certs-{}-{name}", std::process::id()));
+        fs::create_dir_all(certs_dir(&dir)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_server_cert_reused_on_second_call() {
+        let dir = temp_dir("reuse");
+        let ca = generate_ca().unwrap();
+        let first = generate_server_cert(&ca, &dir).unwrap();
+        let second = generate_server_cert(&ca, &dir).unwrap();
+        assert_eq!(first.cert_pem, second.cert_pem);
+        assert_eq!(first.key_pem, second.key_pem);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_server_cert_regenerated_for_new_ca_or_wrong_key() {
+        let dir = temp_dir("regen");
+        let old_ca = generate_ca().unwrap();
+        let first = generate_server_cert(&old_ca, &dir).unwrap();
+
+        let new_ca = generate_ca().unwrap();
+        let second = generate_server_cert(&new_ca, &dir).unwrap();
+        assert_ne!(first.cert_pem, second.cert_pem);
+        assert!(chains_to_ca(&second.cert_pem, &new_ca.cert_pem));
+
+        // A key that doesn't belong to the cert can't be reused either
+        fs::write(server_key_path(&dir), &first.key_pem).unwrap();
+        assert!(!is_reusable(&second.cert_pem, &first.key_pem, &new_ca.cert_pem));
+        let third = generate_server_cert(&new_ca, &dir).unwrap();
+        assert_ne!(third.cert_pem, second.cert_pem);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_macos_script_escapes_quotes_in_path() {
+        let script = macos_install_script("/Users/o'brien/Data \"x\"/ca.pem");
+        assert_eq!(
+            script,
+            r#"do shell script "security add-trusted-cert -d -r trustRoot -k /Library/Keychains/System.keychain '/Users/o'\\''brien/Data \"x\"/ca.pem'" with administrator privileges"#
+        );
+    }
+
+    #[test]
+    fn test_shell_quote_round_trips() {
+        let path = r#"/tmp/it's "odd" $HOME `id` \ ;rm"#;
+        let output = std::process::Command::new("sh")
+            .args(["-c", &format!("printf %s {}", shell_quote(path))])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), path);
+    }
+}
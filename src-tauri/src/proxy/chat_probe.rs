@@ -0,0 +1,174 @@
+use serde::Serialize;
+
+use crate::proxy::config_proxy::ConfigStats;
+
+/// How far chat got through the proxy since launch. Updated by every
+/// connection; read once to tell the user whether chat came up.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatProbe {
+    /// A client connected to the listener at all.
+    pub accepted: bool,
+    /// Last TLS handshake with the client that failed.
+    pub client_tls_error: Option<String>,
+    /// Last failure reaching the upstream chat server.
+    pub upstream_error: Option<String>,
+    /// Chat host of the first established upstream tunnel.
+    pub tunnel_host: Option<String>,
+}
+
+/// Likely reason chat never came up through the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFailure {
+    /// The client never connected: it didn't use our config, or reached
+    /// chat some other way.
+    Bypassed,
+    /// The client connected but rejected our certificate.
+    UntrustedCa,
+    /// The client connected but no chat server could be reached.
+    Unreachable,
+    /// Riot's config had no chat settings for the account, so the client
+    /// never tries to connect. Not something the proxy can fix.
+    ChatUnavailable,
+}
+
+impl ChatFailure {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ChatFailure::Bypassed => {
+                "The Riot Client never connected to the chat proxy — it may have \
+                 bypassed the config proxy"
+            }
+            ChatFailure::UntrustedCa => {
+                "The Riot Client rejected the proxy's certificate — is the CA installed?"
+            }
+            ChatFailure::Unreachable => "The Riot chat server could not be reached",
+            ChatFailure::ChatUnavailable => {
+                "Chat is unavailable for this account or region — Riot sent no chat config"
+            }
+        }
+    }
+}
+
+/// Payload of the `chat-failed` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatFailed {
+    pub cause: ChatFailure,
+    pub message: String,
+}
+
+/// None once a tunnel is up; otherwise the most specific cause seen.
+/// Upstream failures win over client TLS errors, since getting that far means
+/// the client did accept a connection.
+pub fn diagnose(probe: &ChatProbe) -> Option<ChatFailure> {
+    if probe.tunnel_host.is_some() {
+        return None;
+    }
+    if probe.upstream_error.is_some() {
+        return Some(ChatFailure::Unreachable);
+    }
+    if probe.client_tls_error.is_some() {
+        return Some(ChatFailure::UntrustedCa);
+    }
+    if probe.accepted {
+        // Connected and gave up before finishing the handshake
+        return Some(ChatFailure::UntrustedCa);
+    }
+    Some(ChatFailure::Bypassed)
+}
+
+/// `chat-failed` payload for a probe that timed out, with the last error
+/// seen appended when there is one. No connection after config without any
+/// chat settings is chat being off for the account, not a bypass.
+pub fn failure_event(probe: &ChatProbe, config: &ConfigStats) -> Option<ChatFailed> {
+    let cause = match diagnose(probe)? {
+        ChatFailure::Bypassed if config.chat_config_present() == Some(false) => {
+            ChatFailure::ChatUnavailable
+        }
+        cause => cause,
+    };
+    let detail = match cause {
+        ChatFailure::Unreachable => probe.upstream_error.as_deref(),
+        ChatFailure::UntrustedCa => probe.client_tls_error.as_deref(),
+        ChatFailure::Bypassed | ChatFailure::ChatUnavailable => None,
+    };
+    let message = match detail {
+        Some(detail) => format!("{} ({detail})", cause.describe()),
+        None => cause.describe().to_string(),
+    };
+    Some(ChatFailed { cause, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_connection_is_bypass() {
+        assert_eq!(diagnose(&ChatProbe::default()), Some(ChatFailure::Bypassed));
+    }
+
+    #[test]
+    fn test_tls_rejection_is_untrusted_ca() {
+        let probe = ChatProbe {
+            accepted: true,
+            client_tls_error: Some("received fatal alert: UnknownCA".to_string()),
+            ..Default::default()
+        };
+        let event = failure_event(&probe, &ConfigStats::default()).unwrap();
+        assert_eq!(event.cause, ChatFailure::UntrustedCa);
+        assert!(event.message.ends_with("(received fatal alert: UnknownCA)"));
+    }
+
+    #[test]
+    fn test_upstream_failure_is_unreachable() {
+        let probe = ChatProbe {
+            accepted: true,
+            client_tls_error: Some("early eof".to_string()),
+            upstream_error: Some("connection refused".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(diagnose(&probe), Some(ChatFailure::Unreachable));
+    }
+
+    #[test]
+    fn test_tunnel_means_connected() {
+        let probe = ChatProbe {
+            accepted: true,
+            upstream_error: Some("timed out".to_string()),
+            tunnel_host: Some("br1.chat.si.riotgames.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(diagnose(&probe), None);
+        assert!(failure_event(&probe, &ConfigStats::default()).is_none());
+    }
+
+    #[test]
+    fn test_no_chat_config_is_chat_unavailable() {
+        let probe = ChatProbe::default();
+        let no_chat = ConfigStats {
+            patched: 0,
+            passed_through: 3,
+        };
+        let event = failure_event(&probe, &no_chat).unwrap();
+        assert_eq!(event.cause, ChatFailure::ChatUnavailable);
+
+        // Chat config went out, or the client never asked for config at all
+        let redirected = ConfigStats {
+            patched: 1,
+            passed_through: 3,
+        };
+        let cause = failure_event(&probe, &redirected).unwrap().cause;
+        assert_eq!(cause, ChatFailure::Bypassed);
+        let cause = failure_event(&probe, &ConfigStats::default()).unwrap().cause;
+        assert_eq!(cause, ChatFailure::Bypassed);
+
+        // A client that did connect isn't explained by missing config
+        let accepted = ChatProbe {
+            accepted: true,
+            ..Default::default()
+        };
+        let cause = failure_event(&accepted, &no_chat).unwrap().cause;
+        assert_eq!(cause, ChatFailure::UntrustedCa);
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, SystemTime};
+
+/// Skew beyond which TLS starts failing in confusing ways: freshly issued
+/// certs look "not yet valid", or old ones "expired". Well above the
+/// 1-second resolution of the `Date` header plus request latency.
+pub const CLOCK_SKEW_THRESHOLD: Duration = Duration::from_secs(5 * 60);
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Parse an HTTP `Date` header in IMF-fixdate form
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`), the only form servers may send.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let (_, rest) = value.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month_name)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let time = parts.next()?;
+    if parts.next() != Some("GMT") || parts.next().is_some() {
+        return None;
+    }
+
+    let mut hms = time.split(':').map(|n| n.parse::<u64>().ok());
+    let (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) =
+        (hms.next(), hms.next(), hms.next(), hms.next())
+    else {
+        return None;
+    };
+    if year < 1970 || !(1..=31).contains(&day) || h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+
+    let secs = days_since_epoch(year, month, day) * 86_400 + h * 3600 + m * 60 + s;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Days from 1970-01-01 to a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil`, for years from 1970).
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds the local clock is ahead of the server (negative: behind), if
+/// that is more than `CLOCK_SKEW_THRESHOLD`.
+pub fn significant_skew(server: SystemTime, local: SystemTime) -> Option<i64> {
+    let skew = match local.duration_since(server) {
+        Ok(ahead) => ahead.as_secs() as i64,
+        Err(behind) => -(behind.duration().as_secs() as i64),
+    };
+    (skew.unsigned_abs() > CLOCK_SKEW_THRESHOLD.as_secs()).then_some(skew)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_date() {
+        let t = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(t, SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777));
+
+        let leap = parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT").unwrap();
+        assert_eq!(leap, SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_164_800));
+    }
+
+    #[test]
+    fn test_parse_http_date_rejects_other_forms() {
+        // RFC 850 and asctime forms are obsolete; servers must not send them
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn test_significant_skew() {
+        let server = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(significant_skew(server, server + Duration::from_secs(30)), None);
+        assert_eq!(significant_skew(server, server + Duration::from_secs(3600)), Some(3600));
+        assert_eq!(significant_skew(server, server - Duration::from_secs(600)), Some(-600));
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::proxy::stanza::Stanza;
+
+/// How long the server gets to echo an injected unavailable presence back.
+pub const UNAVAILABLE_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Quiet period after a mode change before it is applied. Rapid toggles
+/// (a flaky hotkey, repeated tray clicks) collapse into one injection.
+pub const TOGGLE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Random delay range applied before injecting presence on a mode toggle, so
+/// the flip doesn't look instantaneous. Zero (the default) injects right away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct InjectionJitter {
+    pub min_ms: u64,
+    pub max_ms: u64,
+}
+
+impl InjectionJitter {
+    /// Pick a delay uniformly within the range.
+    pub fn sample(&self) -> Duration {
+        if self.max_ms <= self.min_ms {
+            return Duration::from_millis(self.min_ms);
+        }
+        // RandomState is seeded randomly per instance — enough entropy for a
+        // cosmetic delay without pulling in a rand dependency.
+        let r = RandomState::new().build_hasher().finish();
+        Duration::from_millis(self.min_ms + r % (self.max_ms - self.min_ms + 1))
+    }
+}
+
+/// A delayed injection waiting for its deadline. Scheduling a new one
+/// replaces (cancels) whatever was pending.
+#[derive(Default)]
+pub struct PendingInjection {
+    pending: Option<(Instant, String)>,
+}
+
+impl PendingInjection {
+    pub fn schedule(&mut self, stanza: String, delay: Duration) {
+        self.pending = Some((Instant::now() + delay, stanza));
+    }
+
+    pub fn cancel(&mut self) {
+        self.pending = None;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// When the pending stanza is due. Returns now if nothing is pending.
+    pub fn deadline(&self) -> Instant {
+        self.pending
+            .as_ref()
+            .map(|(deadline, _)| *deadline)
+            .unwrap_or_else(Instant::now)
+    }
+
+    pub fn take(&mut self) -> Option<String> {
+        self.pending.take().map(|(_, stanza)| stanza)
+    }
+}
+
+/// Coalesces mode changes: each one restarts the window, and only the mode
+/// wanted when it closes is applied.
+pub struct ModeDebounce<M> {
+    pending: Option<(Instant, M)>,
+}
+
+impl<M> Default for ModeDebounce<M> {
+    fn default() -> Self {
+        Self { pending: None }
+    }
+}
+
+impl<M: PartialEq> ModeDebounce<M> {
+    pub fn push(&mut self, mode: M, window: Duration) {
+        self.pending = Some((Instant::now() + window, mode));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// When the window closes. Returns now if nothing is pending.
+    pub fn deadline(&self) -> Instant {
+        self.pending
+            .as_ref()
+            .map(|(deadline, _)| *deadline)
+            .unwrap_or_else(Instant::now)
+    }
+
+    /// The settled mode, or None if it's the one already applied (the
+    /// toggles cancelled out).
+    pub fn take(&mut self, applied: &M) -> Option<M> {
+        self.pending
+            .take()
+            .map(|(_, mode)| mode)
+            .filter(|mode| mode != applied)
+    }
+}
+
+/// Whitespace keepalive (RFC 6120 §4.6.1): legal between top-level stanzas,
+/// ignored by the server.
+pub const KEEPALIVE: &str = " ";
+
+/// Optional whitespace keepalive towards the server while the client is idle,
+/// keeping NAT mappings alive and surfacing a dead upstream on the write.
+pub struct Keepalive {
+    interval: Option<Duration>,
+    next: Instant,
+}
+
+impl Keepalive {
+    pub fn new(interval: Option<Duration>) -> Self {
+        let next = Instant::now() + interval.unwrap_or_default();
+        Self { interval, next }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// When the connection counts as idle.
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+
+    /// Client traffic went out: the connection isn't idle.
+    pub fn touch(&mut self) {
+        self.next = Instant::now() + self.interval.unwrap_or_default();
+    }
+
+    /// The deadline passed. Returns whether to send a keepalive now: not
+    /// while a stanza is half-forwarded, which it would split. Re-arms either way.
+    pub fn fire(&mut self, mid_stanza: bool) -> bool {
+        self.touch();
+        self.is_enabled() && !mid_stanza
+    }
+}
+
+/// Result of an unavailable-ack deadline passing.
+#[derive(Debug, PartialEq)]
+pub enum AckOutcome {
+    Acked,
+    /// No echo yet: inject this stanza again and keep waiting.
+    Retry(String),
+    /// Still no echo after the retry. The user may still be visible.
+    Unacknowledged,
+}
+
+/// Opt-in check that an injected unavailable presence reached the server,
+/// which echoes it back to the account's own connection. Injected before the
+/// session is established, the server drops it silently and friends keep
+/// seeing the user. One re-injection is tried before giving up.
+#[derive(Default)]
+pub struct UnavailableAck {
+    /// Set by the server → client direction when the echo is seen.
+    acked: Arc<AtomicBool>,
+    awaiting: Option<AwaitingAck>,
+}
+
+struct AwaitingAck {
+    deadline: Instant,
+    stanza: String,
+    retried: bool,
+}
+
+impl UnavailableAck {
+    /// Flag for the server → client direction to set on the echo.
+    pub fn acked_flag(&self) -> Arc<AtomicBool> {
+        self.acked.clone()
+    }
+
+    /// Start waiting for an echo of `stanza` if it is an unavailable
+    /// presence. Anything else (e.g. switching back to Online) stops waiting.
+    pub fn track(&mut self, stanza: &str, timeout: Duration) {
+        let unavailable = Stanza::parse(stanza)
+            .is_some_and(|s| s.is("presence") && s.attr("type") == Some("unavailable"));
+        if !unavailable {
+            self.awaiting = None;
+            return;
+        }
+        self.acked.store(false, Ordering::SeqCst);
+        self.awaiting = Some(AwaitingAck {
+            deadline: Instant::now() + timeout,
+            stanza: stanza.to_string(),
+            retried: false,
+        });
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.awaiting.is_some()
+    }
+
+    /// When to check for the echo. Returns now if nothing is awaited.
+    pub fn deadline(&self) -> Instant {
+        self.awaiting
+            .as_ref()
+            .map(|a| a.deadline)
+            .unwrap_or_else(Instant::now)
+    }
+
+    /// Called once the deadline passes.
+    pub fn check(&mut self, timeout: Duration) -> AckOutcome {
+        let Some(awaiting) = self.awaiting.take() else {
+            return AckOutcome::Acked;
+        };
+        if self.acked.load(Ordering::SeqCst) {
+            return AckOutcome::Acked;
+        }
+        if awaiting.retried {
+            return AckOutcome::Unacknowledged;
+        }
+        self.awaiting = Some(AwaitingAck {
+            deadline: Instant::now() + timeout,
+            stanza: awaiting.stanza.clone(),
+            retried: true,
+        });
+        AckOutcome::Retry(awaiting.stanza)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_zero_by_default() {
+        assert_eq!(InjectionJitter::default().sample(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_jitter_within_range() {
+        let jitter = InjectionJitter {
+            min_ms: 100,
+            max_ms: 200,
+        };
+        for _ in 0..50 {
+            let d = jitter.sample();
+            assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn test_new_mode_change_cancels_pending_injection() {
+        let mut pending = PendingInjection::default();
+        pending.schedule(
+            r#"<presence type="unavailable"/>"#.to_string(),
+            Duration::from_secs(2),
+        );
+        pending.schedule("<presence/>".to_string(), Duration::from_secs(1));
+
+        assert_eq!(pending.take().as_deref(), Some("<presence/>"));
+        assert!(!pending.is_pending());
+    }
+
+    #[test]
+    fn test_cancel_clears_pending_injection() {
+        let mut pending = PendingInjection::default();
+        pending.schedule("<presence/>".to_string(), Duration::from_secs(1));
+        pending.cancel();
+        assert_eq!(pending.take(), None);
+    }
+
+    #[test]
+    fn test_rapid_toggles_settle_on_final_mode() {
+        let mut debounce = ModeDebounce::default();
+        for mode in ["offline", "online", "offline", "online", "offline"] {
+            debounce.push(mode, TOGGLE_DEBOUNCE);
+        }
+        assert!(debounce.is_pending());
+
+        // One injection, for the last mode
+        assert_eq!(debounce.take(&"online"), Some("offline"));
+        assert_eq!(debounce.take(&"online"), None);
+    }
+
+    #[test]
+    fn test_toggles_back_to_applied_mode_inject_nothing() {
+        let mut debounce = ModeDebounce::default();
+        debounce.push("offline", TOGGLE_DEBOUNCE);
+        debounce.push("online", TOGGLE_DEBOUNCE);
+        assert_eq!(debounce.take(&"online"), None);
+        assert!(!debounce.is_pending());
+    }
+
+    #[test]
+    fn test_idle_connection_gets_periodic_keepalives() {
+        let interval = Duration::from_millis(20);
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(Some(interval));
+
+        let mut sent = 0;
+        while sent < 3 {
+            std::thread::sleep(keepalive.deadline().saturating_duration_since(Instant::now()));
+            if keepalive.fire(false) {
+                sent += 1;
+            }
+        }
+        assert!(start.elapsed() >= interval * 3);
+    }
+
+    #[test]
+    fn test_keepalive_waits_for_stanza_boundary() {
+        let mut keepalive = Keepalive::new(Some(Duration::ZERO));
+        assert!(!keepalive.fire(true));
+        assert!(keepalive.fire(false));
+    }
+
+    #[test]
+    fn test_keepalive_disabled() {
+        let mut keepalive = Keepalive::new(None);
+        assert!(!keepalive.is_enabled());
+        assert!(!keepalive.fire(false));
+    }
+
+    #[test]
+    fn test_client_traffic_postpones_keepalive() {
+        let mut keepalive = Keepalive::new(Some(Duration::from_secs(30)));
+        let first = keepalive.deadline();
+        std::thread::sleep(Duration::from_millis(5));
+        keepalive.touch();
+        assert!(keepalive.deadline() > first);
+    }
+
+    const UNAVAILABLE: &str = r#"<presence type="unavailable"/>"#;
+
+    #[test]
+    fn test_ack_received() {
+        let mut ack = UnavailableAck::default();
+        ack.track(UNAVAILABLE, Duration::ZERO);
+        assert!(ack.is_waiting());
+        ack.acked_flag().store(true, Ordering::SeqCst);
+        assert_eq!(ack.check(Duration::ZERO), AckOutcome::Acked);
+        assert!(!ack.is_waiting());
+    }
+
+    #[test]
+    fn test_missing_ack_retries_once_then_gives_up() {
+        let mut ack = UnavailableAck::default();
+        ack.track(UNAVAILABLE, Duration::ZERO);
+        assert_eq!(
+            ack.check(Duration::ZERO),
+            AckOutcome::Retry(UNAVAILABLE.to_string())
+        );
+        assert!(ack.is_waiting());
+        assert_eq!(ack.check(Duration::ZERO), AckOutcome::Unacknowledged);
+        assert!(!ack.is_waiting());
+    }
+
+    #[test]
+    fn test_ack_not_tracked_for_available_presence() {
+        let mut ack = UnavailableAck::default();
+        ack.track(UNAVAILABLE, Duration::ZERO);
+        ack.track("<presence><show>chat</show></presence>", Duration::ZERO);
+        assert!(!ack.is_waiting());
+    }
+
+    #[test]
+    fn test_new_injection_resets_ack() {
+        let mut ack = UnavailableAck::default();
+        ack.acked_flag().store(true, Ordering::SeqCst);
+        ack.track(UNAVAILABLE, Duration::ZERO);
+        assert_eq!(
+            ack.check(Duration::ZERO),
+            AckOutcome::Retry(UNAVAILABLE.to_string())
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use rustls::pki_types::CertificateDer;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Issuer name fragments of the public CAs Riot's chat servers are signed by.
+/// Anything else upstream means something on the machine (usually an
+/// antivirus with HTTPS scanning) re-signed the connection.
+const EXPECTED_ISSUERS: &[&str] = &[
+    "Riot Games",
+    "DigiCert",
+    "Let's Encrypt",
+    "Amazon",
+    "GlobalSign",
+    "Sectigo",
+    "Google Trust Services",
+    "GoDaddy",
+];
+
+/// Issuer of the upstream leaf certificate when it is not one Riot uses.
+/// Returns None when the chain looks normal or the cert can't be parsed.
+pub fn unexpected_issuer(peer_certs: &[CertificateDer<'_>]) -> Option<String> {
+    let leaf = peer_certs.first()?;
+    let issuer = issuer_name(leaf)?;
+    (!is_expected_issuer(&issuer)).then_some(issuer)
+}
+
+fn issuer_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, parsed) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    Some(parsed.issuer().to_string())
+}
+
+fn is_expected_issuer(issuer: &str) -> bool {
+    let issuer = issuer.to_ascii_lowercase();
+    EXPECTED_ISSUERS
+        .iter()
+        .any(|expected| issuer.contains(&expected.to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+
+    fn self_signed(org: &str, cn: &str) -> CertificateDer<'static> {
+        let mut params =
+            CertificateParams::new(vec!["na2.chat.si.riotgames.com".into()]).unwrap();
+        let mut dn = DistinguishedName::new();
+        dn.push(DnType::OrganizationName, org);
+        dn.push(DnType::CommonName, cn);
+        params.distinguished_name = dn;
+        let key = KeyPair::generate().unwrap();
+        params.self_signed(&key).unwrap().der().clone()
+    }
+
+    #[test]
+    fn test_antivirus_issuer_detected() {
+        let cert = self_signed(
+            "AO Kaspersky Lab",
+            "Kaspersky Anti-Virus Personal Root Certificate",
+        );
+        let issuer = unexpected_issuer(&[cert]).unwrap();
+        assert!(issuer.contains("Kaspersky"));
+    }
+
+    #[test]
+    fn test_public_ca_issuer_accepted() {
+        let cert = self_signed("DigiCert Inc", "DigiCert Global G2 TLS RSA SHA256 2020 CA1");
+        assert_eq!(unexpected_issuer(&[cert]), None);
+    }
+
+    #[test]
+    fn test_no_peer_certs() {
+        assert_eq!(unexpected_issuer(&[]), None);
+    }
+}
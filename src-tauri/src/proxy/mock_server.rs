@@ -0,0 +1,182 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use crate::proxy::presence;
+use crate::proxy::xmpp_proxy;
+
+/// JID handed out by the mock server's resource bind.
+pub const MOCK_JID: &str = "tester@localhost/wit";
+
+pub struct MockServerHandle {
+    pub port: u16,
+    pub shutdown_tx: watch::Sender<bool>,
+}
+
+/// Start a local XMPP server that mimics the parts of Riot's handshake the
+/// client needs (stream features, SASL success, resource bind) and echoes every
+/// other stanza back. Test mode points the XMPP proxy at it so the filtering
+/// pipeline can be watched without touching Riot's servers.
+pub async fn start_mock_server(
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<MockServerHandle, String> {
+    let acceptor = xmpp_proxy::tls_acceptor_from_pem(cert_pem, key_pem)?;
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to bind mock server: {e}"))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to get mock server address: {e}"))?
+        .port();
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+
+    log::info!("Mock XMPP server listening on 127.0.0.1:{port}");
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                result = listener.accept() => {
+                    let Ok((tcp_stream, _)) = result else {
+                        continue;
+                    };
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        match acceptor.accept(tcp_stream).await {
+                            Ok(tls) => serve(tls).await,
+                            Err(e) => log::warn!("Mock server TLS accept failed: {e}"),
+                        }
+                    });
+                }
+                _ = shutdown_rx.changed() => {
+                    log::info!("Mock XMPP server stopped");
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(MockServerHandle { port, shutdown_tx })
+}
+
+async fn serve<S>(stream: S)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let (mut read, mut write) = tokio::io::split(stream);
+    let mut buf = vec![0u8; 8192];
+    let mut pending = String::new();
+
+    loop {
+        let n = match read.read(&mut buf).await {
+            // The client is done: close our side cleanly too
+            Ok(0) => {
+                let _ = write.shutdown().await;
+                break;
+            }
+            Err(_) => break,
+            Ok(n) => n,
+        };
+        pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+
+        let mut consumed = 0;
+        let mut replies = String::new();
+        while let Some(end) = presence::find_stanza_end(&pending[consumed..]) {
+            let stanza = &pending[consumed..consumed + end];
+            consumed += end;
+            if stanza.trim_start().starts_with("<presence") {
+                log::info!("Mock server received presence: {}", stanza.trim());
+            }
+            if let Some(reply) = mock_reply(stanza) {
+                replies.push_str(&reply);
+            }
+        }
+        pending.drain(..consumed);
+
+        if !replies.is_empty() && write.write_all(replies.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// The mock server's answer to one client stanza.
+fn mock_reply(stanza: &str) -> Option<String> {
+    let trimmed = stanza.trim_start();
+    if trimmed.starts_with("<?xml") || trimmed.starts_with("</stream:stream") {
+        return None;
+    }
+    if trimmed.starts_with("<stream:stream") {
+        return Some(
+            "<?xml version='1.0'?><stream:stream xmlns='jabber:client' \
+             xmlns:stream='http://etherx.jabber.org/streams' from='localhost' \
+             id='wit-mock' version='1.0'><stream:features>\
+             <mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>\
+             <mechanism>X-Riot-RSO-PAS</mechanism></mechanisms>\
+             <bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'/>\
+             <session xmlns='urn:ietf:params:xml:ns:xmpp-session'/>\
+             </stream:features>"
+                .to_string(),
+        );
+    }
+    if trimmed.starts_with("<auth") {
+        return Some("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>".to_string());
+    }
+    if trimmed.starts_with("<iq") && trimmed.contains("<bind") {
+        let id = iq_id(trimmed).unwrap_or_default();
+        return Some(format!(
+            "<iq id='{id}' type='result'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'>\
+             <jid>{MOCK_JID}</jid></bind></iq>"
+        ));
+    }
+    if trimmed.starts_with("<iq") {
+        let id = iq_id(trimmed).unwrap_or_default();
+        return Some(format!("<iq id='{id}' type='result'/>"));
+    }
+    Some(stanza.to_string())
+}
+
+fn iq_id(stanza: &str) -> Option<&str> {
+    for quote in ['"', '\''] {
+        let pattern = format!("id={quote}");
+        if let Some(start) = stanza.find(&pattern) {
+            let rest = &stanza[start + pattern.len()..];
+            return rest.find(quote).map(|end| &rest[..end]);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::session;
+
+    #[test]
+    fn test_mock_bind_returns_jid() {
+        let reply = mock_reply(
+            r#"<iq type="set" id="_xmpp_bind1"><bind xmlns="urn:ietf:params:xml:ns:xmpp-bind"/></iq>"#,
+        )
+        .unwrap();
+        assert!(reply.contains("id='_xmpp_bind1'"));
+        assert_eq!(
+            session::extract_bound_jid(&reply).as_deref(),
+            Some("tester@localhost")
+        );
+    }
+
+    #[test]
+    fn test_mock_handshake() {
+        let features = mock_reply("<stream:stream to='localhost' version='1.0'>").unwrap();
+        assert!(features.contains("<stream:features>"));
+        assert!(mock_reply("<auth mechanism='X-Riot-RSO-PAS'>token</auth>")
+            .unwrap()
+            .starts_with("<success"));
+        assert_eq!(mock_reply("<?xml version='1.0'?>"), None);
+    }
+
+    #[test]
+    fn test_mock_echoes_presence() {
+        let stanza = r#"<presence type="unavailable"/>"#;
+        assert_eq!(mock_reply(stanza).as_deref(), Some(stanza));
+    }
+}
@@ -1,49 +1,112 @@
+pub mod capture;
 pub mod certs;
+pub mod chat_probe;
+pub mod clock;
 pub mod config_proxy;
+pub mod injection;
+pub mod interference;
+pub mod mock_server;
 pub mod presence;
+pub mod presence_store;
+pub mod session;
+pub mod stanza;
 pub mod xmpp_proxy;
 
+use std::net::SocketAddr;
+use std::sync::Arc;
+
 use tokio::sync::watch;
 
+use crate::proxy::chat_probe::ChatProbe;
+use crate::proxy::xmpp_proxy::UpstreamRoots;
 use crate::state::StealthMode;
 
 pub struct ProxyHandle {
+    /// Where the listener is bound; the port chat config must point at.
+    pub listen_addr: SocketAddr,
     pub shutdown_tx: watch::Sender<bool>,
     pub mode_tx: watch::Sender<StealthMode>,
-    pub host_tx: watch::Sender<String>,
+    /// Upstream chat host for new connections. Shared so the host can be
+    /// corrected later (`refresh_chat_host`).
+    pub host_tx: Arc<watch::Sender<String>>,
+    /// The address the listener actually bound, once it is accepting
+    /// connections. None before that and after shutdown.
+    pub ready_rx: watch::Receiver<Option<SocketAddr>>,
+    /// Issuer of an unexpected upstream certificate, set when something
+    /// between us and Riot is intercepting TLS.
+    pub interference_rx: watch::Receiver<Option<String>>,
+    /// How far chat connections have got, for the post-launch check.
+    pub probe_rx: watch::Receiver<ChatProbe>,
+    /// Set to sever chat: open connections are closed and new ones refused
+    /// until it is cleared again.
+    pub chat_disconnected_tx: watch::Sender<bool>,
+    /// Signal to re-inject the current mode's presence on every connection.
+    pub reapply_tx: watch::Sender<()>,
+    /// Trust roots the upstream connector loaded.
+    pub upstream_roots: UpstreamRoots,
 }
 
-/// Start the XMPP proxy with the given certs and remote server.
+/// Start the XMPP proxy with the given config and remote server, returning
+/// once it is listening (or with the error that kept it from binding).
 /// Returns a handle to control the proxy (shutdown, toggle stealth, update host).
 pub async fn start_proxy(
+    config: xmpp_proxy::ProxyConfig,
     remote_host: String,
-    remote_port: u16,
-    server_cert_pem: String,
-    server_key_pem: String,
-    ca_cert_pem: String,
     initial_mode: StealthMode,
 ) -> Result<ProxyHandle, String> {
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
     let (mode_tx, mode_rx) = watch::channel(initial_mode);
-    let (host_tx, host_rx) = watch::channel(remote_host.clone());
-
-    let config = xmpp_proxy::ProxyConfig {
-        listen_addr: "127.0.0.1:5223".to_string(),
-        remote_port,
-        server_cert_pem,
-        server_key_pem,
-        ca_cert_pem,
-    };
+    let (host_tx, host_rx) = watch::channel(remote_host);
+    let (ready_tx, ready_rx) = watch::channel(None);
+    let (interference_tx, interference_rx) = watch::channel(None);
+    let (probe_tx, probe_rx) = watch::channel(ChatProbe::default());
+    let (chat_disconnected_tx, chat_disconnected_rx) = watch::channel(false);
+    let (roots_tx, roots_rx) = watch::channel(UpstreamRoots::default());
+    let (reapply_tx, reapply_rx) = watch::channel(());
 
-    tokio::spawn(async move {
-        if let Err(e) = xmpp_proxy::run_proxy(config, host_rx, mode_rx, shutdown_rx).await {
+    let task = tokio::spawn(async move {
+        let result = xmpp_proxy::run_proxy(
+            config,
+            host_rx,
+            mode_rx,
+            shutdown_rx,
+            ready_tx,
+            interference_tx,
+            probe_tx,
+            chat_disconnected_rx,
+            roots_tx,
+            reapply_rx,
+        )
+        .await;
+        if let Err(e) = &result {
             log::error!("Proxy exited with error: {e}");
         }
+        result
     });
 
+    // run_proxy drops the ready sender without ever setting it when it can't bind
+    let mut ready = ready_rx.clone();
+    let bound = match ready.wait_for(Option::is_some).await {
+        Ok(addr) => *addr,
+        Err(_) => None,
+    };
+    let Some(listen_addr) = bound else {
+        return Err(match task.await {
+            Ok(Err(e)) => e,
+            _ => "XMPP proxy stopped before it was listening".to_string(),
+        });
+    };
+
     Ok(ProxyHandle {
+        listen_addr,
         shutdown_tx,
         mode_tx,
-        host_tx,
+        host_tx: Arc::new(host_tx),
+        ready_rx,
+        interference_rx,
+        probe_rx,
+        chat_disconnected_tx,
+        reapply_tx,
+        upstream_roots: roots_rx.borrow().clone(),
     })
 }
@@ -1,175 +1,437 @@
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::stanza::{Attribute, Stanza};
 use crate::state::StealthMode;
 
-/// Filter outgoing XMPP stanzas. When stealth mode is Offline,
-/// replace <presence> stanzas with an "unavailable" type.
-/// All other stanzas pass through unmodified.
-pub fn filter_outgoing(stanza: &str, mode: &StealthMode) -> String {
-    if *mode == StealthMode::Online {
-        return stanza.to_string();
-    }
+pub use crate::proxy::stanza::find_stanza_end;
 
-    let trimmed = stanza.trim();
+/// Whether a contact should see our real presence while invisible.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Visibility {
+    Visible,
+    #[default]
+    Hidden,
+}
 
-    // Only intercept <presence stanzas
-    if !trimmed.starts_with("<presence") {
-        return stanza.to_string();
+/// What Offline mode does with the client's own presence. Directed presence
+/// (with a `to`) is usually the client answering a probe; broadcast presence
+/// (no `to`) goes to the whole roster.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum OfflinePresence {
+    /// Rewrite every presence, broadcast or directed, as unavailable.
+    #[default]
+    Unavailable,
+    /// Drop broadcast presence; answer directed presence with unavailable.
+    AnswerDirected,
+    /// Drop every presence, sending nothing at all.
+    DropAll,
+    /// Keep the account's own resources (the client UI) on the full presence
+    /// while friends see it offline: broadcast presence is re-addressed to the
+    /// account's own bare JID (see `mirror_to_self`), directed presence to
+    /// anyone else is made unavailable.
+    MirrorToSelf,
+}
+
+/// Per-contact visibility rules applied in Offline mode. Patterns are JIDs
+/// with optional `*` wildcards; the blocklist wins over the allowlist, and
+/// contacts matching neither get `default_action`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VisibilityRules {
+    pub allow: Vec<String>,
+    pub block: Vec<String>,
+    pub default_action: Visibility,
+}
+
+impl VisibilityRules {
+    pub fn visibility_for(&self, jid: &str) -> Visibility {
+        let bare = jid.split('/').next().unwrap_or(jid);
+        if self.block.iter().any(|p| wildcard_match(p, bare)) {
+            Visibility::Hidden
+        } else if self.allow.iter().any(|p| wildcard_match(p, bare)) {
+            Visibility::Visible
+        } else {
+            self.default_action
+        }
     }
 
-    // Self-closing presence: <presence ... />
-    if trimmed.ends_with("/>") {
-        return make_unavailable_self_closing(trimmed);
+    /// Literal (non-wildcard) JIDs from `list` that resolve to `target`.
+    /// Only these can receive directed presence, since wildcards can't be
+    /// expanded without the roster.
+    fn literal_jids(&self, list: &[String], target: Visibility) -> Vec<String> {
+        list.iter()
+            .filter(|p| !p.contains('*') && self.visibility_for(p) == target)
+            .cloned()
+            .collect()
     }
+}
 
-    // Full presence stanza: <presence ...> ... </presence>
-    if trimmed.contains("</presence>") {
-        return make_unavailable(trimmed);
+/// Case-insensitive glob match supporting `*` only.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<usize> = None;
+    let mut mark = 0;
+
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            mark = ti;
+            pi += 1;
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some(sp) = star {
+            pi = sp + 1;
+            mark += 1;
+            ti = mark;
+        } else {
+            return false;
+        }
     }
 
-    // If it doesn't match expected patterns, pass through
-    stanza.to_string()
+    p[pi..].iter().all(|&c| c == '*')
 }
 
-/// Replace a self-closing <presence .../> with type="unavailable".
-fn make_unavailable_self_closing(stanza: &str) -> String {
-    // Remove existing type attribute if present
-    let without_type = remove_attribute(stanza, "type");
-    // Insert type="unavailable" after <presence
-    without_type.replacen("<presence", r#"<presence type="unavailable""#, 1)
+/// One step of outgoing stanza filtering. A filter sees one client stanza, or
+/// whatever the filter before it produced, and returns what to send in its
+/// place: usually one stanza, possibly several concatenated, or an empty
+/// string to drop it.
+pub trait PresenceFilter {
+    fn apply(&self, stanza: &str) -> String;
 }
 
-/// Replace a full <presence>...</presence> with a minimal unavailable stanza.
-fn make_unavailable(stanza: &str) -> String {
-    // Extract the opening tag to preserve 'to', 'from', 'id' attributes
-    let tag_end = stanza.find('>').unwrap_or(stanza.len());
-    let opening = &stanza[..tag_end];
-
-    // Remove existing type attribute, add unavailable
-    let without_type = remove_attribute(opening, "type");
-    format!(r#"{} type="unavailable"/>"#, without_type.trim_end_matches('/'))
+/// Filters applied in order, each to the previous one's output. Build one per
+/// stanza from the current mode and settings with `for_mode`, or compose
+/// filters by hand with `with`.
+#[derive(Default)]
+pub struct PresencePipeline<'a> {
+    filters: Vec<Box<dyn PresenceFilter + 'a>>,
 }
 
-/// Remove an XML attribute from a tag string.
-fn remove_attribute(tag: &str, attr: &str) -> String {
-    // Match: attr="value" or attr='value'
-    let patterns = [
-        format!(r#" {}=""#, attr),
-        format!(r#" {}='"#, attr),
-    ];
+impl<'a> PresencePipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, filter: impl PresenceFilter + 'a) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
 
-    for pat in &patterns {
-        if let Some(start) = tag.find(pat.as_str()) {
-            let quote = tag.as_bytes()[start + pat.len() - 1] as char;
-            let value_start = start + pat.len();
-            if let Some(end) = tag[value_start..].find(quote) {
-                let mut result = String::with_capacity(tag.len());
-                result.push_str(&tag[..start]);
-                result.push_str(&tag[value_start + end + 1..]);
-                return result;
+    /// The default pipeline: nothing while Online; the `<show>` rewrite in
+    /// Away, Dnd and ExtendedAway; in Offline the presence rewrite under
+    /// per-contact `rules` and, with `strict_offline`, the activity scrub.
+    /// `account` is only needed for `OfflinePresence::MirrorToSelf`.
+    pub fn for_mode(
+        mode: &StealthMode,
+        rules: &'a VisibilityRules,
+        policy: OfflinePresence,
+        account: &'a str,
+        strict_offline: bool,
+    ) -> Self {
+        if let Some(show) = mode.show() {
+            return Self::new().with(ShowRewrite { show });
+        }
+        if !mode.is_hidden() {
+            return Self::new();
+        }
+        let pipeline = Self::new().with(ContactRules {
+            rules,
+            policy,
+            account,
+        });
+        if strict_offline {
+            pipeline.with(ActivityScrub { rules })
+        } else {
+            pipeline
+        }
+    }
+
+    pub fn apply(&self, stanza: &str) -> String {
+        let mut out = stanza.to_string();
+        for filter in &self.filters {
+            if out.is_empty() {
+                break;
             }
+            out = filter.apply(&out);
         }
+        out
     }
+}
 
-    tag.to_string()
+/// Replace presence stanzas with an "unavailable" type, or drop them,
+/// depending on `policy` and whether they are directed. All other stanzas
+/// pass through unmodified.
+pub struct OfflineRewrite {
+    pub policy: OfflinePresence,
 }
 
-/// Find the end of a complete XMPP stanza in a buffer.
-/// Returns the byte index just past the closing tag, or None if incomplete.
-pub fn find_stanza_end(buffer: &str) -> Option<usize> {
-    let trimmed = buffer.trim_start();
-    if trimmed.is_empty() {
-        return None;
+impl PresenceFilter for OfflineRewrite {
+    fn apply(&self, stanza: &str) -> String {
+        // Only intercept complete <presence> stanzas; anything else (including
+        // input that doesn't parse) passes through
+        let Some(parsed) = Stanza::parse(stanza).filter(|s| s.is("presence")) else {
+            return stanza.to_string();
+        };
+
+        let directed = parsed.attr("to").is_some();
+        match (self.policy, directed) {
+            (OfflinePresence::DropAll, _)
+            | (OfflinePresence::AnswerDirected, false)
+            | (OfflinePresence::MirrorToSelf, false) => {
+                return String::new();
+            }
+            _ => {}
+        }
+
+        make_unavailable(&parsed)
     }
+}
 
-    let offset = buffer.len() - trimmed.len();
+/// Set `<show>` on available presence, replacing the client's own or adding
+/// one, so friends see the account as away or busy. Presence with a `type`
+/// (unavailable, subscriptions, probes) and other stanzas pass through.
+pub struct ShowRewrite {
+    pub show: &'static str,
+}
 
-    // XML processing instructions: <?xml ... ?>
-    if trimmed.starts_with("<?") {
-        if let Some(pos) = trimmed.find("?>") {
-            return Some(offset + pos + 2);
+impl PresenceFilter for ShowRewrite {
+    fn apply(&self, stanza: &str) -> String {
+        let Some(parsed) = Stanza::parse(stanza).filter(|s| s.is("presence")) else {
+            return stanza.to_string();
+        };
+        if parsed.attr("type").is_some() {
+            return stanza.to_string();
         }
-        return None;
+        let show = format!("<show>{}</show>", self.show);
+        parsed.with_first_child(&show, |c| !c.is("show"))
     }
+}
+
+/// `OfflineRewrite` with per-contact rules. Directed presence (with `to`)
+/// passes unchanged to visible contacts and is rewritten for hidden ones.
+/// Broadcast presence is suppressed and re-sent as directed presence to each
+/// literal allowed JID — or, when the default is Visible, passed through and
+/// followed by a directed unavailable to each literal blocked JID. Under
+/// `OfflinePresence::MirrorToSelf` a broadcast is also re-addressed to
+/// `account` (see `mirror_to_self`).
+pub struct ContactRules<'a> {
+    pub rules: &'a VisibilityRules,
+    pub policy: OfflinePresence,
+    pub account: &'a str,
+}
+
+impl PresenceFilter for ContactRules<'_> {
+    fn apply(&self, stanza: &str) -> String {
+        let Some(parsed) = Stanza::parse(stanza).filter(|s| s.is("presence")) else {
+            return stanza.to_string();
+        };
+        let rewrite = OfflineRewrite {
+            policy: self.policy,
+        };
+        let rules = self.rules;
+
+        let mut out = if let Some(to) = parsed.attr("to") {
+            match rules.visibility_for(to) {
+                Visibility::Visible => stanza.to_string(),
+                Visibility::Hidden => rewrite.apply(stanza),
+            }
+        } else {
+            match rules.default_action {
+                Visibility::Hidden => {
+                    let mut out = rewrite.apply(stanza);
+                    for jid in rules.literal_jids(&rules.allow, Visibility::Visible) {
+                        out.push_str(&with_to(&parsed, &jid));
+                    }
+                    out
+                }
+                Visibility::Visible => {
+                    let mut out = stanza.to_string();
+                    for jid in rules.literal_jids(&rules.block, Visibility::Hidden) {
+                        out.push_str(&rewrite.apply(&with_to(&parsed, &jid)));
+                    }
+                    out
+                }
+            }
+        };
 
-    // Closing tags like </stream:stream>
-    if trimmed.starts_with("</") {
-        if let Some(pos) = trimmed.find('>') {
-            return Some(offset + pos + 1);
+        if self.policy == OfflinePresence::MirrorToSelf {
+            if let Some(own) = mirror_to_self(stanza, self.account) {
+                out.insert_str(0, &own);
+            }
         }
-        return None;
+        out
     }
+}
 
-    // Must start with '<' for an opening tag
-    if !trimmed.starts_with('<') {
-        // Non-XML data — forward up to the next '<' or end of buffer
-        return Some(offset + trimmed.find('<').unwrap_or(trimmed.len()));
-    }
+/// Offline filtering with per-contact rules; see `ContactRules`.
+pub fn filter_outgoing_with_rules(
+    stanza: &str,
+    mode: &StealthMode,
+    rules: &VisibilityRules,
+    policy: OfflinePresence,
+) -> String {
+    PresencePipeline::for_mode(mode, rules, policy, "", false).apply(stanza)
+}
+
+/// Turn a broadcast presence into a presence directed at `jid`.
+fn with_to(stanza: &Stanza, jid: &str) -> String {
+    stanza.with_leading_attribute(Attribute {
+        name: "to",
+        value: jid,
+        quote: '"',
+    })
+}
+
+/// Filter outgoing XMPP stanzas through the default pipeline with no
+/// per-contact rules: in Offline, presence is rewritten per `policy` (see
+/// `OfflineRewrite`); everything else passes through unmodified.
+pub fn filter_outgoing(stanza: &str, mode: &StealthMode, policy: OfflinePresence) -> String {
+    PresencePipeline::for_mode(mode, &VisibilityRules::default(), policy, "", false).apply(stanza)
+}
 
-    // Self-closing tags: <tag ... />
-    if let Some(pos) = find_self_closing_end(trimmed) {
-        return Some(offset + pos);
+/// For `OfflinePresence::MirrorToSelf`: an available broadcast presence
+/// re-addressed to the account's own bare JID. XMPP servers deliver presence
+/// directed at the user's bare JID to all of the user's own available
+/// resources (RFC 6121 §4.6) and to no contact, while broadcast presence
+/// (no `to`) is what reaches the roster. Sent in place of the broadcast, the
+/// client keeps its rich presence and friends get nothing. Caveat: the
+/// server only probes contacts after an initial broadcast, so friends'
+/// presence may not arrive either. None for anything else, or while the
+/// account isn't known yet.
+pub fn mirror_to_self(stanza: &str, account: &str) -> Option<String> {
+    let parsed = Stanza::parse(stanza).filter(|s| s.is("presence"))?;
+    if account.is_empty() || parsed.attr("to").is_some() || parsed.attr("type").is_some() {
+        return None;
     }
+    Some(with_to(&parsed, account))
+}
+
+/// Namespaces of message children that reveal activity rather than content:
+/// chat states (XEP-0085), delivery receipts (XEP-0184) and chat markers
+/// (XEP-0333).
+const ACTIVITY_NAMESPACES: &[&str] = &[
+    "http://jabber.org/protocol/chatstates",
+    "urn:xmpp:receipts",
+    "urn:xmpp:chat-markers:0",
+];
+
+/// Strict offline: strip typing notifications and receipts from outgoing
+/// `<message>` stanzas, so chatting doesn't show you as active. Message
+/// bodies still go out; a message left with nothing but activity children is
+/// dropped. Contacts visible under `rules` are left alone.
+pub struct ActivityScrub<'a> {
+    pub rules: &'a VisibilityRules,
+}
 
-    // Extract the tag name to find its closing tag dynamically
-    let tag_name = extract_tag_name(trimmed)?;
+impl PresenceFilter for ActivityScrub<'_> {
+    fn apply(&self, stanza: &str) -> String {
+        let Some(parsed) = Stanza::parse(stanza).filter(|s| s.is("message")) else {
+            return stanza.to_string();
+        };
+        if let Some(to) = parsed.attr("to") {
+            if self.rules.visibility_for(to) == Visibility::Visible {
+                return stanza.to_string();
+            }
+        }
 
-    // <stream:stream> is a stream-level open — ends at '>', never closed in same stanza
-    if tag_name == "stream:stream" {
-        if let Some(pos) = trimmed.find('>') {
-            return Some(offset + pos + 1);
+        let is_activity =
+            |c: &Stanza| c.attr("xmlns").is_some_and(|ns| ACTIVITY_NAMESPACES.contains(&ns));
+        let children = parsed.children();
+        if !children.is_empty() && children.iter().all(is_activity) {
+            return String::new();
         }
-        return None;
+        parsed.retain_children(|c| !is_activity(c))
     }
+}
 
-    // Look for the matching closing tag </tagname>
-    let close_tag = format!("</{tag_name}>");
-    if let Some(pos) = trimmed.find(&close_tag) {
-        return Some(offset + pos + close_tag.len());
+/// `ActivityScrub` while invisible; the visible modes pass everything through.
+pub fn scrub_activity(stanza: &str, mode: &StealthMode, rules: &VisibilityRules) -> String {
+    if !mode.is_hidden() {
+        return stanza.to_string();
     }
+    ActivityScrub { rules }.apply(stanza)
+}
 
-    None
+/// Stanza to inject into a live connection when the stealth mode changes.
+/// Offline sends an unavailable presence; Online re-sends the last cached
+/// presence, or a bare `<presence/>` if none was captured yet. Away, Dnd and
+/// ExtendedAway send the same with their `<show>` set.
+pub fn injection_for_mode(mode: &StealthMode, last_presence: &str) -> Option<String> {
+    if mode.is_hidden() {
+        return Some(r#"<presence type="unavailable"/>"#.to_string());
+    }
+    let presence = if last_presence.is_empty() {
+        "<presence/>"
+    } else {
+        last_presence
+    };
+    match mode.show() {
+        Some(show) => Some(ShowRewrite { show }.apply(presence)),
+        None => Some(presence.to_string()),
+    }
 }
 
-/// Extract the element name from an opening tag (e.g. "<auth " → "auth").
-fn extract_tag_name(s: &str) -> Option<&str> {
-    let after_lt = &s[1..]; // skip '<'
-    let end = after_lt.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
-    if end == 0 {
-        return None;
+/// What a live connection does when the mode changes, for previews.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToggleSimulation {
+    /// Stanza injected upstream, if any.
+    pub inject: Option<String>,
+    /// Whether the injection re-sends the cached last presence.
+    pub restores_last_presence: bool,
+}
+
+/// The actions the proxy takes on a `from` → `to` transition with
+/// `last_presence` cached, via `injection_for_mode`. Like the proxy, a
+/// "transition" to the mode already applied does nothing.
+pub fn simulate_toggle(
+    from: &StealthMode,
+    to: &StealthMode,
+    last_presence: &str,
+) -> ToggleSimulation {
+    if from == to {
+        return ToggleSimulation {
+            inject: None,
+            restores_last_presence: false,
+        };
+    }
+    ToggleSimulation {
+        inject: injection_for_mode(to, last_presence),
+        restores_last_presence: !to.is_hidden() && !last_presence.is_empty(),
     }
-    Some(&after_lt[..end])
 }
 
-/// Find end of a self-closing opening tag like `<presence ... />`.
-/// Only matches `/>` that belongs to the root element — if we see a bare `>`
-/// first (closing the opening tag), the element has body content and is NOT
-/// self-closing, so we return None.
-fn find_self_closing_end(buffer: &str) -> Option<usize> {
-    let mut in_quotes = false;
-    let mut quote_char = '"';
-
-    for (i, ch) in buffer.char_indices() {
-        match ch {
-            '"' | '\'' if !in_quotes => {
-                in_quotes = true;
-                quote_char = ch;
-            }
-            c if c == quote_char && in_quotes => {
-                in_quotes = false;
-            }
-            '/' if !in_quotes => {
-                if buffer[i + 1..].starts_with('>') {
-                    return Some(i + 2);
-                }
-            }
-            '>' if !in_quotes => {
-                // A bare '>' before any '/>' means the opening tag closed and
-                // element has body content — not a self-closing tag.
-                return None;
-            }
-            _ => {}
+/// Replace a presence with a minimal unavailable one, keeping the opening
+/// tag's attributes ('to', 'from', 'id', namespaced ones like 'xml:lang') and
+/// dropping the body. Only `type` changes: an existing one is rewritten in
+/// place (duplicates dropped), otherwise it is appended.
+fn make_unavailable(stanza: &Stanza) -> String {
+    let mut attributes = Vec::with_capacity(stanza.attributes.len() + 1);
+    let mut has_type = false;
+    for attribute in &stanza.attributes {
+        if attribute.name != "type" {
+            attributes.push(*attribute);
+        } else if !has_type {
+            attributes.push(Attribute {
+                value: "unavailable",
+                ..*attribute
+            });
+            has_type = true;
         }
     }
-    None
+    if !has_type {
+        attributes.push(Attribute {
+            name: "type",
+            value: "unavailable",
+            quote: '"',
+        });
+    }
+    stanza.render_empty(attributes)
 }
 
 #[cfg(test)]
@@ -177,16 +439,18 @@ mod tests {
     use super::*;
     use crate::state::StealthMode;
 
+    const UNAVAILABLE: OfflinePresence = OfflinePresence::Unavailable;
+
     #[test]
     fn test_filter_online_passthrough() {
         let stanza = r#"<presence><show>chat</show></presence>"#;
-        assert_eq!(filter_outgoing(stanza, &StealthMode::Online), stanza);
+        assert_eq!(filter_outgoing(stanza, &StealthMode::Online, UNAVAILABLE), stanza);
     }
 
     #[test]
     fn test_filter_offline_full_presence() {
         let stanza = r#"<presence from="user@server" to="friend@server"><show>chat</show><status>Playing</status></presence>"#;
-        let result = filter_outgoing(stanza, &StealthMode::Offline);
+        let result = filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE);
         assert!(result.contains(r#"type="unavailable""#));
         assert!(result.contains(r#"from="user@server""#));
         assert!(!result.contains("<show>"));
@@ -195,15 +459,443 @@ mod tests {
     #[test]
     fn test_filter_offline_self_closing() {
         let stanza = r#"<presence from="user@server"/>"#;
-        let result = filter_outgoing(stanza, &StealthMode::Offline);
+        let result = filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE);
         assert!(result.contains(r#"type="unavailable""#));
         assert!(result.contains(r#"from="user@server""#));
     }
 
+    #[test]
+    fn test_filter_offline_concatenated_self_closing() {
+        // Presence bursts can arrive back to back with no whitespace between
+        let buf = r#"<presence/><presence type="unavailable"/><presence to="duo@br1.pvp.net"/>"#;
+        let mut stanzas = Vec::new();
+        let mut rest = buf;
+        while let Some(end) = find_stanza_end(rest) {
+            stanzas.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        assert_eq!(rest, "");
+        assert_eq!(
+            stanzas,
+            [
+                "<presence/>",
+                r#"<presence type="unavailable"/>"#,
+                r#"<presence to="duo@br1.pvp.net"/>"#,
+            ]
+        );
+
+        for (stanza, expected) in stanzas.iter().zip([
+            r#"<presence type="unavailable"/>"#,
+            r#"<presence type="unavailable"/>"#,
+            r#"<presence to="duo@br1.pvp.net" type="unavailable"/>"#,
+        ]) {
+            assert_eq!(filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE), expected);
+        }
+    }
+
     #[test]
     fn test_filter_non_presence_passthrough() {
         let stanza = r#"<message to="friend@server"><body>hello</body></message>"#;
-        assert_eq!(filter_outgoing(stanza, &StealthMode::Offline), stanza);
+        assert_eq!(filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE), stanza);
+    }
+
+    #[test]
+    fn test_injection_offline() {
+        let cached = r#"<presence><show>chat</show></presence>"#;
+        assert_eq!(
+            injection_for_mode(&StealthMode::Offline, cached).as_deref(),
+            Some(r#"<presence type="unavailable"/>"#)
+        );
+    }
+
+    #[test]
+    fn test_injection_online_without_cache() {
+        assert_eq!(
+            injection_for_mode(&StealthMode::Online, "").as_deref(),
+            Some("<presence/>")
+        );
+    }
+
+    #[test]
+    fn test_injection_online_restores_cached() {
+        let cached = r#"<presence id='5'><show>chat</show><status>Playing</status></presence>"#;
+        assert_eq!(
+            injection_for_mode(&StealthMode::Online, cached).as_deref(),
+            Some(cached)
+        );
+    }
+
+    #[test]
+    fn test_injection_show_modes() {
+        let cached = r#"<presence id='5'><show>chat</show><status>Playing</status></presence>"#;
+        assert_eq!(
+            injection_for_mode(&StealthMode::Away, cached).as_deref(),
+            Some("<presence id='5'><show>away</show><status>Playing</status></presence>")
+        );
+        assert_eq!(
+            injection_for_mode(&StealthMode::Dnd, "").as_deref(),
+            Some("<presence><show>dnd</show></presence>")
+        );
+        let toggle = simulate_toggle(&StealthMode::Offline, &StealthMode::ExtendedAway, cached);
+        assert!(toggle.restores_last_presence);
+        assert!(toggle.inject.unwrap().contains("<show>xa</show>"));
+    }
+
+    #[test]
+    fn test_filter_show_modes_rewrite_show() {
+        let rich = r#"<presence><show>chat</show><status>in queue</status></presence>"#;
+        assert_eq!(
+            filter_outgoing(rich, &StealthMode::Away, UNAVAILABLE),
+            "<presence><show>away</show><status>in queue</status></presence>"
+        );
+        // Inserted when the client sent none
+        let plain = "<presence id='1'><status/></presence>";
+        assert_eq!(
+            filter_outgoing(plain, &StealthMode::Dnd, UNAVAILABLE),
+            "<presence id='1'><show>dnd</show><status/></presence>"
+        );
+        // Also into a self-closing one, broadcast or directed
+        assert_eq!(
+            filter_outgoing("<presence/>", &StealthMode::Dnd, UNAVAILABLE),
+            "<presence><show>dnd</show></presence>"
+        );
+        assert_eq!(
+            filter_outgoing(r#"<presence to="friend@server"/>"#, &StealthMode::Dnd, UNAVAILABLE),
+            r#"<presence to="friend@server"><show>dnd</show></presence>"#
+        );
+        // Typed presence and other stanzas are left alone
+        let unavailable = r#"<presence type="unavailable"/>"#;
+        assert_eq!(filter_outgoing(unavailable, &StealthMode::Away, UNAVAILABLE), unavailable);
+        let message = "<message to='a@b'><body>brb</body></message>";
+        assert_eq!(filter_outgoing(message, &StealthMode::Away, UNAVAILABLE), message);
+    }
+
+    const DIRECTED: &str = r#"<presence to="friend@server"><show>chat</show></presence>"#;
+    const BROADCAST: &str = r#"<presence><show>chat</show></presence>"#;
+
+    #[test]
+    fn test_simulate_toggle() {
+        let cached = r#"<presence><show>chat</show></presence>"#;
+        let (online, offline) = (StealthMode::Online, StealthMode::Offline);
+
+        let going_offline = simulate_toggle(&online, &offline, cached);
+        assert_eq!(going_offline.inject.as_deref(), Some(r#"<presence type="unavailable"/>"#));
+        assert!(!going_offline.restores_last_presence);
+
+        let going_online = simulate_toggle(&offline, &online, cached);
+        assert_eq!(going_online.inject.as_deref(), Some(cached));
+        assert!(going_online.restores_last_presence);
+
+        let nothing_cached = simulate_toggle(&offline, &online, "");
+        assert_eq!(nothing_cached.inject.as_deref(), Some("<presence/>"));
+        assert!(!nothing_cached.restores_last_presence);
+
+        assert_eq!(simulate_toggle(&offline, &offline, cached).inject, None);
+    }
+
+    #[test]
+    fn test_policy_unavailable_rewrites_both() {
+        for stanza in [DIRECTED, BROADCAST] {
+            let result = filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE);
+            assert!(result.contains(r#"type="unavailable""#));
+        }
+    }
+
+    #[test]
+    fn test_policy_answer_directed() {
+        let policy = OfflinePresence::AnswerDirected;
+        assert_eq!(
+            filter_outgoing(DIRECTED, &StealthMode::Offline, policy),
+            r#"<presence to="friend@server" type="unavailable"/>"#
+        );
+        assert_eq!(filter_outgoing(BROADCAST, &StealthMode::Offline, policy), "");
+    }
+
+    #[test]
+    fn test_policy_drop_all() {
+        let policy = OfflinePresence::DropAll;
+        assert_eq!(filter_outgoing(DIRECTED, &StealthMode::Offline, policy), "");
+        assert_eq!(filter_outgoing(BROADCAST, &StealthMode::Offline, policy), "");
+    }
+
+    #[test]
+    fn test_policy_ignored_online() {
+        for policy in [OfflinePresence::AnswerDirected, OfflinePresence::DropAll] {
+            assert_eq!(filter_outgoing(DIRECTED, &StealthMode::Online, policy), DIRECTED);
+            assert_eq!(filter_outgoing(BROADCAST, &StealthMode::Online, policy), BROADCAST);
+        }
+    }
+
+    #[test]
+    fn test_policy_answer_directed_keeps_allowed_resend() {
+        let r = rules(&["duo@br1.pvp.net"], &[], Visibility::Hidden);
+        let result = filter_outgoing_with_rules(
+            BROADCAST,
+            &StealthMode::Offline,
+            &r,
+            OfflinePresence::AnswerDirected,
+        );
+        assert_eq!(result, r#"<presence to="duo@br1.pvp.net"><show>chat</show></presence>"#);
+    }
+
+    fn rules(allow: &[&str], block: &[&str], default_action: Visibility) -> VisibilityRules {
+        VisibilityRules {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            block: block.iter().map(|s| s.to_string()).collect(),
+            default_action,
+        }
+    }
+
+    #[test]
+    fn test_rules_block_wins_over_allow() {
+        let r = rules(&["*@br1.pvp.net"], &["rival@br1.pvp.net"], Visibility::Hidden);
+        assert_eq!(r.visibility_for("friend@br1.pvp.net"), Visibility::Visible);
+        assert_eq!(r.visibility_for("rival@br1.pvp.net/RC-1"), Visibility::Hidden);
+        assert_eq!(r.visibility_for("other@na1.pvp.net"), Visibility::Hidden);
+    }
+
+    #[test]
+    fn test_rules_default_visible() {
+        let r = rules(&[], &["ex@br1.pvp.net"], Visibility::Visible);
+        assert_eq!(r.visibility_for("friend@br1.pvp.net"), Visibility::Visible);
+        assert_eq!(r.visibility_for("ex@br1.pvp.net"), Visibility::Hidden);
+    }
+
+    #[test]
+    fn test_rules_directed_presence_to_allowed() {
+        let r = rules(&["duo@br1.pvp.net"], &[], Visibility::Hidden);
+        let stanza = r#"<presence to="duo@br1.pvp.net"><show>chat</show></presence>"#;
+        assert_eq!(
+            filter_outgoing_with_rules(stanza, &StealthMode::Offline, &r, UNAVAILABLE),
+            stanza
+        );
+    }
+
+    #[test]
+    fn test_rules_directed_presence_to_blocked() {
+        let r = rules(&["*"], &["ex@br1.pvp.net"], Visibility::Hidden);
+        let stanza = r#"<presence to="ex@br1.pvp.net"><show>chat</show></presence>"#;
+        let result = filter_outgoing_with_rules(stanza, &StealthMode::Offline, &r, UNAVAILABLE);
+        assert!(result.contains(r#"type="unavailable""#));
+        assert!(!result.contains("<show>"));
+    }
+
+    #[test]
+    fn test_rules_broadcast_resent_to_allowed() {
+        let r = rules(&["duo@br1.pvp.net", "*@team"], &[], Visibility::Hidden);
+        let stanza = r#"<presence><show>chat</show></presence>"#;
+        let result = filter_outgoing_with_rules(stanza, &StealthMode::Offline, &r, UNAVAILABLE);
+        assert!(result.starts_with(r#"<presence type="unavailable"/>"#));
+        assert!(result.ends_with(r#"<presence to="duo@br1.pvp.net"><show>chat</show></presence>"#));
+        assert!(!result.contains("@team"));
+    }
+
+    #[test]
+    fn test_rules_empty_matches_plain_filter() {
+        let stanza = r#"<presence from="user@server"><show>chat</show></presence>"#;
+        assert_eq!(
+            filter_outgoing_with_rules(
+                stanza,
+                &StealthMode::Offline,
+                &VisibilityRules::default(),
+                UNAVAILABLE
+            ),
+            filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE)
+        );
+    }
+
+    const MIRROR: OfflinePresence = OfflinePresence::MirrorToSelf;
+
+    #[test]
+    fn test_mirror_to_self_readdresses_broadcast() {
+        let presence = "<presence id='3'><show>chat</show><games><keystone/></games></presence>";
+        assert_eq!(
+            mirror_to_self(presence, "me@br1.pvp.net").as_deref(),
+            Some(r#"<presence to="me@br1.pvp.net" id='3'><show>chat</show><games><keystone/></games></presence>"#)
+        );
+        // Friends get nothing from the broadcast itself
+        assert_eq!(filter_outgoing(presence, &StealthMode::Offline, MIRROR), "");
+    }
+
+    #[test]
+    fn test_mirror_to_self_leaves_other_presence() {
+        let directed = r#"<presence to="duo@br1.pvp.net"><show>chat</show></presence>"#;
+        assert_eq!(mirror_to_self(directed, "me@br1.pvp.net"), None);
+        assert_eq!(
+            filter_outgoing(directed, &StealthMode::Offline, MIRROR),
+            r#"<presence to="duo@br1.pvp.net" type="unavailable"/>"#
+        );
+
+        assert_eq!(mirror_to_self(r#"<presence type="unavailable"/>"#, "me@br1.pvp.net"), None);
+        assert_eq!(mirror_to_self("<presence/>", ""), None);
+    }
+
+    const COMPOSING: &str = "<composing xmlns='http://jabber.org/protocol/chatstates'/>";
+
+    #[test]
+    fn test_scrub_composing_keeps_body() {
+        let message = format!(
+            "<message to='duo@br1.pvp.net' type='chat'><body>gg</body>{COMPOSING}\
+             <request xmlns='urn:xmpp:receipts'/></message>"
+        );
+        assert_eq!(
+            scrub_activity(&message, &StealthMode::Offline, &VisibilityRules::default()),
+            "<message to='duo@br1.pvp.net' type='chat'><body>gg</body></message>"
+        );
+    }
+
+    #[test]
+    fn test_scrub_drops_bare_typing_notification() {
+        let message = format!("<message to='duo@br1.pvp.net' type='chat'>{COMPOSING}</message>");
+        let rules = VisibilityRules::default();
+        assert_eq!(scrub_activity(&message, &StealthMode::Offline, &rules), "");
+        assert_eq!(scrub_activity(&message, &StealthMode::Online, &rules), message);
+    }
+
+    #[test]
+    fn test_scrub_skips_visible_contacts_and_other_stanzas() {
+        let message = format!("<message to='duo@br1.pvp.net' type='chat'>{COMPOSING}</message>");
+        let rules = rules(&["duo@br1.pvp.net"], &[], Visibility::Hidden);
+        assert_eq!(scrub_activity(&message, &StealthMode::Offline, &rules), message);
+
+        let iq = r#"<iq type="get" id="1"><query xmlns="jabber:iq:roster"/></iq>"#;
+        let rules = VisibilityRules::default();
+        assert_eq!(scrub_activity(iq, &StealthMode::Offline, &rules), iq);
+    }
+
+    #[test]
+    fn test_pipeline_empty_while_online() {
+        let rules = VisibilityRules::default();
+        let pipeline = PresencePipeline::for_mode(&StealthMode::Online, &rules, MIRROR, "", true);
+        let message = format!("<message to='a@b'>{COMPOSING}</message>");
+        assert_eq!(pipeline.apply(&message), message);
+        assert_eq!(pipeline.apply("<presence/>"), "<presence/>");
+    }
+
+    #[test]
+    fn test_pipeline_strict_offline_adds_scrub() {
+        let rules = VisibilityRules::default();
+        let message = format!("<message to='a@b' type='chat'>{COMPOSING}</message>");
+        let offline = &StealthMode::Offline;
+        let plain = PresencePipeline::for_mode(offline, &rules, UNAVAILABLE, "", false);
+        assert_eq!(plain.apply(&message), message);
+        let strict = PresencePipeline::for_mode(offline, &rules, UNAVAILABLE, "", true);
+        assert_eq!(strict.apply(&message), "");
+        assert_eq!(strict.apply("<presence/>"), r#"<presence type="unavailable"/>"#);
+    }
+
+    struct Tag(&'static str);
+
+    impl PresenceFilter for Tag {
+        fn apply(&self, stanza: &str) -> String {
+            format!("{stanza}{}", self.0)
+        }
+    }
+
+    #[test]
+    fn test_pipeline_applies_filters_in_order() {
+        let pipeline = PresencePipeline::new().with(Tag("<a/>")).with(Tag("<b/>"));
+        assert_eq!(pipeline.apply("<x/>"), "<x/><a/><b/>");
+
+        // A dropped stanza stays dropped
+        let pipeline = PresencePipeline::new()
+            .with(OfflineRewrite {
+                policy: OfflinePresence::DropAll,
+            })
+            .with(Tag("<a/>"));
+        assert_eq!(pipeline.apply("<presence/>"), "");
+    }
+
+    /// xorshift64*: fixed-seed pseudo-random input, so a fuzz failure
+    /// reproduces without pulling in a rand dependency.
+    struct Fuzz(u64);
+
+    impl Fuzz {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Fragments fuzz inputs are stitched from: XML syntax in all the places
+    /// the parser slices around, plus multi-byte characters.
+    const FUZZ_TOKENS: &[&str] = &[
+        "<", ">", "/", "/>", "</", "<presence", "<presence>", "</presence>", "<show>", "</show>",
+        "<pty/>", "<status ", "</status>", "<stream:stream", "</stream:stream>", "<?xml", "?>",
+        "<!--", "-->", "<![CDATA[", "]]>", " ", "\n", "=", "'", "\"", " type=\"unavailable\"",
+        " to='me@br1.pvp.net'", " from=\"x\"", " xml:lang='en'", "&lt;", "é", "😀", "\0", "a",
+    ];
+
+    /// Split `input` the way the proxy does and filter every stanza, checking
+    /// that nothing panics and the output is well-formed.
+    fn check_stanza_handling(input: &str) {
+        let mut rest = input;
+        while let Some(end) = find_stanza_end(rest) {
+            assert!(end > 0 && end <= rest.len(), "bad end {end} in {rest:?}");
+            assert!(rest.is_char_boundary(end), "end {end} splits a char in {rest:?}");
+            let stanza = &rest[..end];
+            rest = &rest[end..];
+
+            assert_eq!(filter_outgoing(stanza, &StealthMode::Online, UNAVAILABLE), stanza);
+            for policy in [
+                OfflinePresence::Unavailable,
+                OfflinePresence::AnswerDirected,
+                OfflinePresence::DropAll,
+                OfflinePresence::MirrorToSelf,
+            ] {
+                let out = filter_outgoing(stanza, &StealthMode::Offline, policy);
+                if out.is_empty() || out == stanza {
+                    continue;
+                }
+                // Anything rewritten is one complete, bodiless unavailable presence
+                assert_eq!(find_stanza_end(&out), Some(out.len()), "{stanza:?} → {out:?}");
+                let parsed = Stanza::parse(&out).expect("rewritten presence parses");
+                assert!(parsed.is("presence") && parsed.self_closing, "{stanza:?} → {out:?}");
+                assert_eq!(parsed.attr("type"), Some("unavailable"), "{stanza:?} → {out:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz_stanza_handling() {
+        let mut fuzz = Fuzz(0x5EED_7EE0);
+        for _ in 0..20_000 {
+            let len = 1 + fuzz.below(24);
+            let input: String = (0..len)
+                .map(|_| FUZZ_TOKENS[fuzz.below(FUZZ_TOKENS.len())])
+                .collect();
+            check_stanza_handling(&input);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_truncated_and_corrupted_stanzas() {
+        let samples = [
+            r#"<presence from="me@br1.pvp.net/RC" xml:lang='en'><show>chat</show><games><keystone><pty/></keystone></games><status>é 😀</status></presence>"#,
+            r#"<presence to='duo@br1.pvp.net' type="available"/>"#,
+            "<?xml version='1.0'?><stream:stream to='pvp.net'></stream:stream>",
+            r#"<message to="a@b"><body>&lt;presence/&gt;</body></message>"#,
+        ];
+        let mut fuzz = Fuzz(0xC0FF_EE00);
+        for sample in samples {
+            // Every prefix, as if the connection stopped mid-stanza
+            for (i, _) in sample.char_indices() {
+                check_stanza_handling(&sample[..i]);
+            }
+            // Random tokens spliced in at char boundaries
+            let boundaries: Vec<usize> = sample.char_indices().map(|(i, _)| i).collect();
+            for _ in 0..2_000 {
+                let at = boundaries[fuzz.below(boundaries.len())];
+                let token = FUZZ_TOKENS[fuzz.below(FUZZ_TOKENS.len())];
+                check_stanza_handling(&format!("{}{token}{}", &sample[..at], &sample[at..]));
+            }
+        }
     }
 
     #[test]
@@ -233,11 +925,38 @@ mod tests {
     #[test]
     fn test_replace_existing_type() {
         let stanza = r#"<presence type="available" from="user@server"><show>chat</show></presence>"#;
-        let result = filter_outgoing(stanza, &StealthMode::Offline);
+        let result = filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE);
         assert!(result.contains(r#"type="unavailable""#));
         assert!(!result.contains(r#"type="available""#));
     }
 
+    #[test]
+    fn test_unavailable_keeps_namespaced_attributes() {
+        let stanza = r#"<presence xml:lang='en' xmlns="jabber:client" from="me@br1.pvp.net/RC" type="available"><status xml:lang="en">hi</status></presence>"#;
+        assert_eq!(
+            filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE),
+            r#"<presence xml:lang='en' xmlns="jabber:client" from="me@br1.pvp.net/RC" type="unavailable"/>"#
+        );
+    }
+
+    #[test]
+    fn test_unavailable_appends_type_after_namespaced_attributes() {
+        let stanza = r#"<presence xmlns:riot="urn:riot" riot:type="lol" id='p1'><show>chat</show></presence>"#;
+        assert_eq!(
+            filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE),
+            r#"<presence xmlns:riot="urn:riot" riot:type="lol" id='p1' type="unavailable"/>"#
+        );
+    }
+
+    #[test]
+    fn test_unavailable_rewrites_type_in_place() {
+        let stanza = r#"<presence type='available' to="duo@br1.pvp.net" type="probe"/>"#;
+        assert_eq!(
+            filter_outgoing(stanza, &StealthMode::Offline, UNAVAILABLE),
+            r#"<presence type='unavailable' to="duo@br1.pvp.net"/>"#
+        );
+    }
+
     #[test]
     fn test_find_stanza_end_auth() {
         let buf = r#"<auth xmlns="urn:ietf:params:xml:ns:xmpp-sasl" mechanism="X-Riot-RSO">dG9rZW4=</auth>"#;
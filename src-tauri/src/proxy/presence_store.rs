@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::proxy::stanza::Stanza;
+
+/// Children that describe the current game session (queue, champion,
+/// timestamps). Stale by the next app start, so never written to disk.
+const VOLATILE_ELEMENTS: &[&str] = &["games"];
+
+/// Where the last presence is kept when `persist_presence` is on.
+pub fn store_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("last_presence.xml")
+}
+
+/// Reduce a captured presence to what is worth restoring in a later session:
+/// no stanza id and no game state. None for anything but a broadcast
+/// available presence.
+pub fn sanitize(presence: &str) -> Option<String> {
+    let stanza = Stanza::parse(presence).filter(|s| s.is("presence"))?;
+    if stanza.attr("type").is_some() || stanza.attr("to").is_some() {
+        return None;
+    }
+
+    let attributes = stanza.attributes.iter().copied().filter(|a| a.name != "id");
+    let kept: String = stanza
+        .children()
+        .iter()
+        .filter(|c| !VOLATILE_ELEMENTS.contains(&c.name))
+        .map(|c| c.raw.trim())
+        .collect();
+    if kept.is_empty() {
+        return Some(stanza.render_empty(attributes));
+    }
+
+    let open = stanza.render_empty(attributes);
+    let open = open.strip_suffix("/>").unwrap_or(&open);
+    Some(format!("{open}>{kept}</{}>", stanza.name))
+}
+
+/// Sanitize and write `presence`. Presence that isn't worth keeping leaves
+/// the stored one untouched.
+pub fn save(path: &Path, presence: &str) -> Result<(), String> {
+    let Some(sanitized) = sanitize(presence) else {
+        return Ok(());
+    };
+    fs::write(path, sanitized).map_err(|e| format!("Failed to write {:?}: {e}", path))
+}
+
+/// The stored presence, if there is a valid one.
+pub fn load(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let presence = sanitize(content.trim());
+    if presence.is_none() {
+        log::warn!("Ignoring invalid stored presence in {:?}", path);
+    }
+    presence
+}
+
+/// Delete the stored presence. Missing is fine.
+pub fn clear(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove {:?}: {e}", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wit-presence-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_sanitize_drops_id_and_game_state() {
+        let raw = "<presence id='p1'><show>chat</show><status>Duo?</status>\
+                   <games><league_of_legends><st>inGame</st></league_of_legends></games>\
+                   </presence>";
+        assert_eq!(
+            sanitize(raw).as_deref(),
+            Some("<presence><show>chat</show><status>Duo?</status></presence>")
+        );
+    }
+
+    #[test]
+    fn test_sanitize_rejects_other_presence() {
+        assert_eq!(sanitize(r#"<presence type="unavailable"/>"#), None);
+        let directed = r#"<presence to="duo@br1.pvp.net"><show>chat</show></presence>"#;
+        assert_eq!(sanitize(directed), None);
+        assert_eq!(sanitize("<message><body>hi</body></message>"), None);
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let path = temp_path("round-trip");
+        let raw = "<presence id='p2'><show>away</show><games><keystone/></games></presence>";
+        save(&path, raw).unwrap();
+        assert_eq!(load(&path).as_deref(), Some("<presence><show>away</show></presence>"));
+
+        // Unavailable presence doesn't overwrite what was stored
+        save(&path, r#"<presence type="unavailable"/>"#).unwrap();
+        assert_eq!(load(&path).as_deref(), Some("<presence><show>away</show></presence>"));
+
+        clear(&path).unwrap();
+        assert_eq!(load(&path), None);
+        clear(&path).unwrap();
+    }
+}
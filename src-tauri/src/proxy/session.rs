@@ -0,0 +1,582 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::proxy::presence_store;
+use crate::proxy::stanza::{self, Stanza};
+use crate::state::StealthMode;
+
+/// Last available presence per account, shared by every connection in a proxy
+/// session so a client reconnect can still restore the rich presence when
+/// switching back to Online. Keyed by bare JID ("" until the JID is known).
+#[derive(Clone, Default)]
+pub struct PresenceCache {
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    by_account: HashMap<String, String>,
+    latest: Option<String>,
+    /// Presence actually forwarded to the server, after filtering.
+    sent_by_account: HashMap<String, String>,
+    latest_sent: Option<String>,
+    /// Presence stored by an earlier session, used until this session
+    /// captures one. Only set when persisting is on.
+    restored: Option<String>,
+    /// File the captured presence is written to, if persisting is on.
+    store: Option<PathBuf>,
+    /// Sanitized presence in `store`, as last read or written.
+    stored: Option<String>,
+    /// Sanitized presence not yet written to `store`, written by `clear`
+    /// when the session ends.
+    unsaved: Option<String>,
+}
+
+impl PresenceCache {
+    pub fn get(&self, account: &str) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.by_account.get(account).or(inner.restored.as_ref()).cloned()
+    }
+
+    /// The most recently cached presence across all accounts.
+    pub fn latest(&self) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        inner.latest.as_ref().or(inner.restored.as_ref()).cloned()
+    }
+
+    /// Keep the last presence on disk at `path` across app restarts, and
+    /// restore whatever is already stored there. `None` stops persisting.
+    pub fn persist_to(&self, path: Option<PathBuf>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.restored = path.as_deref().and_then(presence_store::load);
+        if inner.restored.is_some() {
+            log::info!("Restored presence from the previous session");
+        }
+        inner.stored = inner.restored.clone();
+        inner.unsaved = None;
+        inner.store = path;
+    }
+
+    /// Cache `presence` for `account`. With persisting on, it's only marked
+    /// for writing, and only if it differs from what's stored: the client
+    /// re-sends presence often, and this runs on the forwarding path.
+    pub fn set(&self, account: &str, presence: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.store.is_some() {
+            if let Some(sanitized) = presence_store::sanitize(&presence) {
+                inner.unsaved = (inner.stored.as_ref() != Some(&sanitized)).then_some(sanitized);
+            }
+        }
+        inner.latest = Some(presence.clone());
+        inner.by_account.insert(account.to_string(), presence);
+    }
+
+    /// Record presence forwarded to the server (filtered or injected), i.e. what
+    /// the server should currently believe about the account.
+    pub fn record_sent(&self, account: &str, presence: String) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.latest_sent = Some(presence.clone());
+        inner.sent_by_account.insert(account.to_string(), presence);
+    }
+
+    /// Last presence sent for `account`, or across all accounts if `None`.
+    pub fn last_sent(&self, account: Option<&str>) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+        match account {
+            Some(account) => inner.sent_by_account.get(account).cloned(),
+            None => inner.latest_sent.clone(),
+        }
+    }
+
+    /// Forget all cached presences. Called when the proxy stops so presence
+    /// data doesn't outlive the session. With persisting on, the last
+    /// captured presence is written first, outside the cache lock.
+    pub fn clear(&self) {
+        let unsaved = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.by_account.clear();
+            inner.latest = None;
+            inner.sent_by_account.clear();
+            inner.latest_sent = None;
+            inner.restored = None;
+            inner.stored = None;
+            let unsaved = inner.unsaved.take();
+            inner.store.take().zip(unsaved)
+        };
+        if let Some((path, presence)) = unsaved {
+            if let Err(e) = presence_store::save(&path, &presence) {
+                log::warn!("Failed to persist presence: {e}");
+            }
+        }
+    }
+}
+
+/// Entries kept in the connection mode log; the oldest are dropped first.
+const MODE_LOG_CAPACITY: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModeEventKind {
+    /// Global mode when the connection was accepted.
+    Accepted,
+    /// Mode the connection was switched to afterwards, per-account overrides
+    /// included.
+    Changed,
+}
+
+/// One entry of the connection mode log, for checking after the fact which
+/// mode a connection was actually in.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionModeEvent {
+    pub connection: u64,
+    /// Unix time in milliseconds.
+    pub at: u64,
+    pub kind: ModeEventKind,
+    pub mode: StealthMode,
+}
+
+/// Number of live client connections, shared between the proxy and app state.
+/// Every change is also published on a watch channel for listeners that need
+/// to react to clients coming and going.
+#[derive(Clone)]
+pub struct ConnectionCounter {
+    count: Arc<AtomicUsize>,
+    changed_tx: Arc<watch::Sender<usize>>,
+    /// Source of per-connection ids used to tag log lines.
+    next_id: Arc<AtomicU64>,
+    mode_log: Arc<Mutex<VecDeque<ConnectionModeEvent>>>,
+}
+
+impl Default for ConnectionCounter {
+    fn default() -> Self {
+        Self {
+            count: Arc::new(AtomicUsize::new(0)),
+            changed_tx: Arc::new(watch::channel(0).0),
+            next_id: Arc::new(AtomicU64::new(1)),
+            mode_log: Arc::default(),
+        }
+    }
+}
+
+impl ConnectionCounter {
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.changed_tx.subscribe()
+    }
+
+    /// Count a new connection until the returned guard is dropped, which also
+    /// happens when the connection task errors out or panics. Each connection
+    /// gets a new id, unique for the life of the app.
+    pub fn enter(&self) -> ConnectionGuard {
+        let n = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        self.changed_tx.send_replace(n);
+        ConnectionGuard {
+            counter: self.clone(),
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    /// Record the mode connection `id` was accepted in or switched to.
+    pub fn log_mode(&self, id: u64, kind: ModeEventKind, mode: &StealthMode) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut log = self.mode_log.lock().unwrap();
+        if log.len() == MODE_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(ConnectionModeEvent {
+            connection: id,
+            at,
+            kind,
+            mode: mode.clone(),
+        });
+    }
+
+    /// Mode log entries, oldest first.
+    pub fn mode_log(&self) -> Vec<ConnectionModeEvent> {
+        self.mode_log.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+pub struct ConnectionGuard {
+    counter: ConnectionCounter,
+    id: u64,
+}
+
+impl ConnectionGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let n = self.counter.count.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.counter.changed_tx.send_replace(n);
+    }
+}
+
+/// Extract the bare JID from a resource-bind result (`<jid>user@host/res</jid>`)
+/// sent by the server. Returns None if the data holds no complete `<jid>`.
+pub fn extract_bound_jid(data: &str) -> Option<String> {
+    let start = data.find("<jid>")? + "<jid>".len();
+    let len = data[start..].find("</jid>")?;
+    let full = data[start..start + len].trim();
+    let bare = full.split('/').next().unwrap_or(full);
+    if bare.is_empty() {
+        return None;
+    }
+    Some(bare.to_string())
+}
+
+/// Whether a server chunk holds the echo of `account`'s own unavailable
+/// presence, i.e. a `<presence type="unavailable">` from its bare JID. An echo
+/// split across two reads is missed, which only costs a re-injection.
+pub fn acks_unavailable(data: &str, account: &str) -> bool {
+    if account.is_empty() {
+        return false;
+    }
+    data.match_indices("<presence").any(|(start, _)| {
+        let rest = &data[start..];
+        let Some(end) = stanza::find_stanza_end(rest) else {
+            return false;
+        };
+        let Some(presence) = Stanza::parse(&rest[..end]) else {
+            return false;
+        };
+        let from = presence.attr("from").unwrap_or_default();
+        let bare = from.split('/').next().unwrap_or(from);
+        presence.attr("type") == Some("unavailable") && bare.eq_ignore_ascii_case(account)
+    })
+}
+
+/// Whether a server chunk accepts stream compression (XEP-0138), i.e. holds
+/// `<compressed xmlns='http://jabber.org/protocol/compress'/>`. Everything
+/// after it is compressed, in both directions.
+pub fn compression_started(data: &str) -> bool {
+    has_element(data, "<compressed", "http://jabber.org/protocol/compress")
+}
+
+/// Whether a server chunk completes SASL authentication, i.e. holds
+/// `<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>`. Before it the
+/// stream is still being negotiated.
+pub fn auth_succeeded(data: &str) -> bool {
+    has_element(data, "<success", "urn:ietf:params:xml:ns:xmpp-sasl")
+}
+
+/// Whether a client stanza is the `</stream:stream>` that ends the session
+/// cleanly. Nothing may follow it on the stream.
+pub fn is_stream_close(stanza: &str) -> bool {
+    Stanza::parse(stanza)
+        .is_some_and(|s| s.kind == stanza::StanzaKind::Close && s.name == "stream:stream")
+}
+
+/// Whether a unit split out of the client stream can be XMPP. Between
+/// stanzas the stream only carries whitespace (keepalives); anything else
+/// there — binary junk, another protocol — is not XMPP and must not reach
+/// the server.
+pub fn is_xmpp_unit(unit: &str) -> bool {
+    match unit.trim_start().chars().next() {
+        Some(c) => c == '<',
+        None => true,
+    }
+}
+
+/// Whether `data` has an `open` tag declaring namespace `ns`.
+fn has_element(data: &str, open: &str, ns: &str) -> bool {
+    let mut rest = data;
+    while let Some(start) = rest.find(open) {
+        let tag = &rest[start..];
+        let tag = &tag[..tag.find('>').map_or(tag.len(), |end| end + 1)];
+        if tag.contains(ns) {
+            return true;
+        }
+        rest = &rest[start + open.len()..];
+    }
+    false
+}
+
+/// Per-account stealth overrides, keyed by bare JID. Accounts without an entry
+/// follow the global mode.
+pub type AccountModes = HashMap<String, StealthMode>;
+
+/// The mode a connection should apply. Several Riot clients can be connected
+/// through the proxy at once, each on its own TCP/TLS connection; the account
+/// is only known once the server confirms resource binding, so until then (and
+/// for accounts with no override) the global mode applies.
+pub fn effective_mode(
+    global: &StealthMode,
+    overrides: &AccountModes,
+    account: &str,
+) -> StealthMode {
+    overrides.get(account).unwrap_or(global).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::presence::injection_for_mode;
+
+    #[test]
+    fn test_extract_bound_jid() {
+        let data = r#"<iq id="_xmpp_bind1" type="result"><bind xmlns="urn:ietf:params:xml:ns:xmpp-bind"><jid>abc-123@br1.pvp.net/RC-1</jid></bind></iq>"#;
+        assert_eq!(extract_bound_jid(data).as_deref(), Some("abc-123@br1.pvp.net"));
+    }
+
+    #[test]
+    fn test_extract_bound_jid_incomplete() {
+        assert_eq!(extract_bound_jid("<bind><jid>abc@br1.pvp"), None);
+    }
+
+    #[test]
+    fn test_reconnect_restores_cached_presence() {
+        let cache = PresenceCache::default();
+        let rich = r#"<presence><show>chat</show><status>Ranked</status></presence>"#;
+
+        // First connection captures the presence, then drops
+        cache.set("abc-123@br1.pvp.net", rich.to_string());
+
+        // Reconnect: a new connection binds the same account and goes Online
+        let cached = cache.get("abc-123@br1.pvp.net").unwrap_or_default();
+        assert_eq!(
+            injection_for_mode(&StealthMode::Online, &cached).as_deref(),
+            Some(rich)
+        );
+    }
+
+    #[test]
+    fn test_clear_forgets_presence() {
+        let cache = PresenceCache::default();
+        cache.set("abc@br1.pvp.net", "<presence/>".to_string());
+        cache.record_sent("abc@br1.pvp.net", "<presence type=\"unavailable\"/>".to_string());
+        cache.clear();
+        assert_eq!(cache.get("abc@br1.pvp.net"), None);
+        assert_eq!(cache.latest(), None);
+        assert_eq!(cache.last_sent(None), None);
+    }
+
+    #[test]
+    fn test_last_sent_presence_per_account() {
+        let cache = PresenceCache::default();
+        let hidden = r#"<presence type="unavailable"/>"#;
+        let rich = r#"<presence><show>chat</show></presence>"#;
+        cache.record_sent("main@br1.pvp.net", hidden.to_string());
+        cache.record_sent("smurf@br1.pvp.net", rich.to_string());
+
+        assert_eq!(cache.last_sent(Some("main@br1.pvp.net")).as_deref(), Some(hidden));
+        assert_eq!(cache.last_sent(None).as_deref(), Some(rich));
+        assert_eq!(cache.last_sent(Some("other@br1.pvp.net")), None);
+    }
+
+    #[test]
+    fn test_effective_mode_prefers_account_override() {
+        let mut overrides = AccountModes::new();
+        overrides.insert("smurf@br1.pvp.net".to_string(), StealthMode::Offline);
+
+        assert_eq!(
+            effective_mode(&StealthMode::Online, &overrides, "smurf@br1.pvp.net"),
+            StealthMode::Offline
+        );
+        assert_eq!(
+            effective_mode(&StealthMode::Online, &overrides, "main@br1.pvp.net"),
+            StealthMode::Online
+        );
+    }
+
+    #[test]
+    fn test_effective_mode_unbound_connection_uses_global() {
+        let mut overrides = AccountModes::new();
+        overrides.insert("smurf@br1.pvp.net".to_string(), StealthMode::Online);
+        assert_eq!(
+            effective_mode(&StealthMode::Offline, &overrides, ""),
+            StealthMode::Offline
+        );
+    }
+
+    #[test]
+    fn test_connection_guard_counts() {
+        let counter = ConnectionCounter::default();
+        let first = counter.enter();
+        let second = counter.enter();
+        assert_eq!(counter.get(), 2);
+
+        drop(first);
+        assert_eq!(counter.get(), 1);
+        drop(second);
+        assert_eq!(counter.get(), 0);
+        assert_eq!(*counter.subscribe().borrow(), 0);
+    }
+
+    #[test]
+    fn test_mode_log_records_accept_and_changes() {
+        let counter = ConnectionCounter::default();
+        let first = counter.enter();
+        counter.log_mode(first.id(), ModeEventKind::Accepted, &StealthMode::Offline);
+        let second = counter.enter();
+        counter.log_mode(second.id(), ModeEventKind::Accepted, &StealthMode::Online);
+        counter.log_mode(first.id(), ModeEventKind::Changed, &StealthMode::Online);
+
+        let log: Vec<_> = counter
+            .mode_log()
+            .into_iter()
+            .map(|e| (e.connection, e.kind, e.mode))
+            .collect();
+        assert_eq!(
+            log,
+            vec![
+                (first.id(), ModeEventKind::Accepted, StealthMode::Offline),
+                (second.id(), ModeEventKind::Accepted, StealthMode::Online),
+                (first.id(), ModeEventKind::Changed, StealthMode::Online),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mode_log_drops_oldest() {
+        let counter = ConnectionCounter::default();
+        for id in 0..MODE_LOG_CAPACITY as u64 + 3 {
+            counter.log_mode(id, ModeEventKind::Accepted, &StealthMode::Offline);
+        }
+        let log = counter.mode_log();
+        assert_eq!(log.len(), MODE_LOG_CAPACITY);
+        assert_eq!(log[0].connection, 3);
+    }
+
+    #[test]
+    fn test_connection_guard_released_on_panic() {
+        let counter = ConnectionCounter::default();
+        let task_counter = counter.clone();
+        let result = std::panic::catch_unwind(move || {
+            let _guard = task_counter.enter();
+            panic!("connection task failed");
+        });
+        assert!(result.is_err());
+        assert_eq!(counter.get(), 0);
+    }
+
+    #[test]
+    fn test_presence_restored_across_sessions() {
+        let path = std::env::temp_dir()
+            .join(format!("wit-session-{}-restore", std::process::id()));
+        let first = PresenceCache::default();
+        first.persist_to(Some(path.clone()));
+        first.set("a@br1.pvp.net", "<presence id='1'><show>chat</show></presence>".into());
+        first.clear();
+
+        // A new session restores it until it captures its own
+        let second = PresenceCache::default();
+        second.persist_to(Some(path.clone()));
+        assert_eq!(
+            second.get("b@br1.pvp.net").as_deref(),
+            Some("<presence><show>chat</show></presence>")
+        );
+        second.set("b@br1.pvp.net", "<presence><show>away</show></presence>".into());
+        assert_eq!(
+            second.get("b@br1.pvp.net").as_deref(),
+            Some("<presence><show>away</show></presence>")
+        );
+
+        second.clear();
+
+        // Re-sending what's stored leaves the file alone
+        let unchanged = PresenceCache::default();
+        unchanged.persist_to(Some(path.clone()));
+        unchanged.set("b@br1.pvp.net", "<presence id='7'><show>away</show></presence>".into());
+        std::fs::remove_file(&path).unwrap();
+        unchanged.clear();
+        assert!(!path.exists());
+
+        // Not persisting: nothing restored
+        let third = PresenceCache::default();
+        third.persist_to(None);
+        assert_eq!(third.get("b@br1.pvp.net"), None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unavailable_echo_detected() {
+        let echo = "<iq type='result' id='1'/><presence from='me@br1.pvp.net/RC-1' \
+                    to='me@br1.pvp.net/RC-1' type='unavailable'/>";
+        assert!(acks_unavailable(echo, "me@br1.pvp.net"));
+        assert!(!acks_unavailable(echo, ""));
+    }
+
+    #[test]
+    fn test_unavailable_echo_ignores_friends_and_available() {
+        let friend = "<presence from='duo@br1.pvp.net/RC-2' type='unavailable'/>";
+        assert!(!acks_unavailable(friend, "me@br1.pvp.net"));
+        let available = "<presence from='me@br1.pvp.net/RC-1'><show>chat</show></presence>";
+        assert!(!acks_unavailable(available, "me@br1.pvp.net"));
+    }
+
+    #[test]
+    fn test_compression_negotiation_detected() {
+        assert!(compression_started(
+            "<compressed xmlns='http://jabber.org/protocol/compress'/>"
+        ));
+        assert!(compression_started(
+            r#"<iq type="result"/><compressed xmlns="http://jabber.org/protocol/compress"/>"#
+        ));
+    }
+
+    #[test]
+    fn test_compression_request_or_failure_not_detected() {
+        // The client's request isn't the switch; only the server's acceptance is
+        assert!(!compression_started(
+            "<compress xmlns='http://jabber.org/protocol/compress'><method>zlib</method></compress>"
+        ));
+        assert!(!compression_started(
+            "<failure xmlns='http://jabber.org/protocol/compress'><setup-failed/></failure>"
+        ));
+        assert!(!compression_started("<message><body><compressed/></body></message>"));
+    }
+
+    #[test]
+    fn test_stream_close_detected() {
+        assert!(is_stream_close("</stream:stream>"));
+        assert!(is_stream_close("\n </stream:stream >"));
+        assert!(!is_stream_close("<stream:stream to='localhost' version='1.0'>"));
+        assert!(!is_stream_close("</presence>"));
+        assert!(!is_stream_close("<presence><status>&lt;/stream:stream&gt;</status></presence>"));
+    }
+
+    #[test]
+    fn test_non_xml_units_rejected() {
+        assert!(is_xmpp_unit("<presence/>"));
+        assert!(is_xmpp_unit(" \n"));
+        assert!(is_xmpp_unit("\n<?xml version='1.0'?>"));
+        assert!(!is_xmpp_unit("\u{16}\u{3}\u{1}\u{0}"));
+        assert!(!is_xmpp_unit("GET / HTTP/1.1\r\n"));
+        assert!(!is_xmpp_unit("\u{FFFD}\u{FFFD}"));
+    }
+
+    #[test]
+    fn test_auth_success_detected() {
+        assert!(auth_succeeded("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>"));
+        assert!(!auth_succeeded(
+            "<failure xmlns='urn:ietf:params:xml:ns:xmpp-sasl'><not-authorized/></failure>"
+        ));
+        assert!(!auth_succeeded(r#"<iq type="result"><success/></iq>"#));
+    }
+
+    #[test]
+    fn test_connection_ids_not_reused() {
+        let counter = ConnectionCounter::default();
+        let first = counter.enter();
+        let first_id = first.id();
+        drop(first);
+        let second = counter.enter();
+        assert!(second.id() > first_id);
+    }
+}
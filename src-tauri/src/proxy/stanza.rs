@@ -0,0 +1,458 @@
+/// What a complete unit from the XMPP stream is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StanzaKind {
+    /// `<?xml ... ?>`
+    Declaration,
+    /// `<stream:stream ...>`, which stays open for the whole session.
+    StreamOpen,
+    /// `</stream:stream>` or any other stray closing tag.
+    Close,
+    /// A complete element: `<name .../>` or `<name ...>...</name>`.
+    Element,
+    /// Character data between elements (whitespace keepalives).
+    Text,
+}
+
+/// One attribute of an element's opening tag, borrowed from the raw stanza.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attribute<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+    pub quote: char,
+}
+
+/// A complete stanza split out of the stream. The typed view (name,
+/// attributes, body, children) is for the filtering logic; `raw` keeps the
+/// exact bytes so passthrough never re-serializes anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stanza<'a> {
+    pub raw: &'a str,
+    pub kind: StanzaKind,
+    /// Element name including any prefix (`stream:features`). Empty for
+    /// declarations and text.
+    pub name: &'a str,
+    pub attributes: Vec<Attribute<'a>>,
+    pub self_closing: bool,
+    /// Everything between the opening and closing tag.
+    pub body: &'a str,
+}
+
+impl<'a> Stanza<'a> {
+    /// Parse one complete stanza, as returned by `find_stanza_end`. Returns
+    /// None if `raw` is not a single complete, well-formed unit.
+    pub fn parse(raw: &'a str) -> Option<Self> {
+        let trimmed = raw.trim();
+        let text = Stanza {
+            raw,
+            kind: StanzaKind::Text,
+            name: "",
+            attributes: Vec::new(),
+            self_closing: false,
+            body: "",
+        };
+
+        if trimmed.is_empty() || !trimmed.starts_with('<') {
+            return Some(text);
+        }
+        if trimmed.starts_with("<?") {
+            return trimmed.ends_with("?>").then_some(Stanza {
+                kind: StanzaKind::Declaration,
+                ..text
+            });
+        }
+        if let Some(rest) = trimmed.strip_prefix("</") {
+            let name = rest.strip_suffix('>')?.trim();
+            return Some(Stanza {
+                kind: StanzaKind::Close,
+                name,
+                ..text
+            });
+        }
+
+        let (name, attributes, tag_len, self_closing) = parse_opening_tag(trimmed)?;
+        if self_closing {
+            return (tag_len == trimmed.len()).then_some(Stanza {
+                kind: StanzaKind::Element,
+                name,
+                attributes,
+                self_closing,
+                ..text
+            });
+        }
+        if name == "stream:stream" {
+            return Some(Stanza {
+                kind: StanzaKind::StreamOpen,
+                name,
+                attributes,
+                ..text
+            });
+        }
+
+        let body = trimmed[tag_len..]
+            .strip_suffix('>')?
+            .trim_end()
+            .strip_suffix(name)?
+            .strip_suffix("</")?;
+        Some(Stanza {
+            kind: StanzaKind::Element,
+            name,
+            attributes,
+            body,
+            ..text
+        })
+    }
+
+    pub fn is(&self, name: &str) -> bool {
+        self.kind == StanzaKind::Element && self.name == name
+    }
+
+    /// Value of an attribute on the opening tag, exactly as written.
+    pub fn attr(&self, name: &str) -> Option<&'a str> {
+        self.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value)
+    }
+
+    /// Direct child elements, in order. Text between them is skipped.
+    pub fn children(&self) -> Vec<Stanza<'a>> {
+        let mut children = Vec::new();
+        let mut rest = self.body;
+        while let Some(end) = find_stanza_end(rest) {
+            if let Some(child) = Stanza::parse(&rest[..end]) {
+                if child.kind == StanzaKind::Element {
+                    children.push(child);
+                }
+            }
+            rest = &rest[end..];
+        }
+        children
+    }
+
+    /// The stanza without the direct children `keep` rejects, along with the
+    /// whitespace leading up to them. Everything else is kept byte for byte;
+    /// the raw stanza is returned as-is when nothing is removed.
+    pub fn retain_children(&self, keep: impl Fn(&Stanza) -> bool) -> String {
+        let Some(body) = self.retained_body(keep) else {
+            return self.raw.to_string();
+        };
+        format!("{}{body}</{}>", self.opening_tag(), self.name)
+    }
+
+    /// The stanza with `child` inserted as its first child, after dropping
+    /// the direct children `keep` rejects as `retain_children` does. A
+    /// self-closing element is opened up to hold it.
+    pub fn with_first_child(&self, child: &str, keep: impl Fn(&Stanza) -> bool) -> String {
+        let body = self.retained_body(keep);
+        let body = body.as_deref().unwrap_or(self.body);
+        format!("{}{child}{body}</{}>", self.opening_tag(), self.name)
+    }
+
+    /// The body without the direct children `keep` rejects, or None when it
+    /// rejects none.
+    fn retained_body(&self, keep: impl Fn(&Stanza) -> bool) -> Option<String> {
+        let mut body = String::with_capacity(self.body.len());
+        let mut removed = false;
+        let mut rest = self.body;
+        while let Some(end) = find_stanza_end(rest) {
+            let unit = &rest[..end];
+            match Stanza::parse(unit) {
+                Some(child) if child.kind == StanzaKind::Element && !keep(&child) => {
+                    removed = true;
+                }
+                _ => body.push_str(unit),
+            }
+            rest = &rest[end..];
+        }
+        if !removed {
+            return None;
+        }
+        body.push_str(rest);
+        Some(body)
+    }
+
+    /// `<name a="v">`, re-serialized like `render_empty`.
+    fn opening_tag(&self) -> String {
+        let open = self.render_empty(self.attributes.iter().copied());
+        let open = open.strip_suffix("/>").unwrap_or(&open);
+        format!("{open}>")
+    }
+
+    /// Serialize as an empty element (`<name a="v"/>`) with the given
+    /// attributes, keeping each attribute's original quoting.
+    pub fn render_empty<'b>(&self, attributes: impl IntoIterator<Item = Attribute<'b>>) -> String {
+        let mut out = format!("<{}", self.name);
+        for a in attributes {
+            out.push_str(&format!(" {}={q}{}{q}", a.name, a.value, q = a.quote));
+        }
+        out.push_str("/>");
+        out
+    }
+
+    /// The raw stanza with `attribute` added as the first attribute of the
+    /// opening tag. Everything after the element name is kept byte for byte.
+    pub fn with_leading_attribute(&self, attribute: Attribute<'_>) -> String {
+        let trimmed = self.raw.trim();
+        let rest = &trimmed[1 + self.name.len()..];
+        format!(
+            "<{} {}={q}{}{q}{rest}",
+            self.name,
+            attribute.name,
+            attribute.value,
+            q = attribute.quote
+        )
+    }
+}
+
+/// Parse `<name attr="v" ...>` or `<name .../>` at the start of `s`. Returns
+/// the name, attributes, length of the tag and whether it is self-closing.
+fn parse_opening_tag(s: &str) -> Option<(&str, Vec<Attribute<'_>>, usize, bool)> {
+    let name = extract_tag_name(s)?;
+    let mut attributes = Vec::new();
+    let mut pos = 1 + name.len();
+
+    loop {
+        let rest = &s[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+
+        if trimmed.starts_with("/>") {
+            return Some((name, attributes, pos + 2, true));
+        }
+        if trimmed.starts_with('>') {
+            return Some((name, attributes, pos + 1, false));
+        }
+
+        let eq = trimmed.find('=')?;
+        let attr_name = trimmed[..eq].trim_end();
+        // A quote or tag delimiter can't be part of a name; accepting one would
+        // let `parse` disagree with `find_stanza_end` about where quotes are
+        let invalid = |c: char| c.is_whitespace() || matches!(c, '<' | '>' | '/' | '\'' | '"');
+        if attr_name.is_empty() || attr_name.contains(invalid) {
+            return None;
+        }
+        let after_eq = trimmed[eq + 1..].trim_start();
+        let quote = after_eq.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value_len = after_eq[1..].find(quote)?;
+        let value = &after_eq[1..1 + value_len];
+
+        attributes.push(Attribute {
+            name: attr_name,
+            value,
+            quote,
+        });
+        pos += (trimmed.len() - after_eq.len()) + value_len + 2;
+    }
+}
+
+/// Find the end of a complete XMPP stanza in a buffer.
+/// Returns the byte index just past the closing tag, or None if incomplete.
+pub fn find_stanza_end(buffer: &str) -> Option<usize> {
+    let trimmed = buffer.trim_start();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    let offset = buffer.len() - trimmed.len();
+
+    // XML processing instructions: <?xml ... ?>
+    if trimmed.starts_with("<?") {
+        if let Some(pos) = trimmed.find("?>") {
+            return Some(offset + pos + 2);
+        }
+        return None;
+    }
+
+    // Closing tags like </stream:stream>
+    if trimmed.starts_with("</") {
+        if let Some(pos) = trimmed.find('>') {
+            return Some(offset + pos + 1);
+        }
+        return None;
+    }
+
+    // Must start with '<' for an opening tag
+    if !trimmed.starts_with('<') {
+        // Non-XML data — forward up to the next '<' or end of buffer
+        return Some(offset + trimmed.find('<').unwrap_or(trimmed.len()));
+    }
+
+    // Self-closing tags: <tag ... />
+    if let Some(pos) = find_self_closing_end(trimmed) {
+        return Some(offset + pos);
+    }
+
+    // Extract the tag name to find its closing tag dynamically
+    let tag_name = extract_tag_name(trimmed)?;
+
+    // <stream:stream> is a stream-level open — ends at '>', never closed in same stanza
+    if tag_name == "stream:stream" {
+        if let Some(pos) = trimmed.find('>') {
+            return Some(offset + pos + 1);
+        }
+        return None;
+    }
+
+    // Look for the matching closing tag </tagname>
+    let close_tag = format!("</{tag_name}>");
+    if let Some(pos) = trimmed.find(&close_tag) {
+        return Some(offset + pos + close_tag.len());
+    }
+
+    None
+}
+
+/// Extract the element name from an opening tag (e.g. "<auth " → "auth").
+fn extract_tag_name(s: &str) -> Option<&str> {
+    let after_lt = &s[1..]; // skip '<'
+    let end = after_lt.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+    if end == 0 {
+        return None;
+    }
+    Some(&after_lt[..end])
+}
+
+/// Find end of a self-closing opening tag like `<presence ... />`.
+/// Only matches `/>` that belongs to the root element — if we see a bare `>`
+/// first (closing the opening tag), the element has body content and is NOT
+/// self-closing, so we return None.
+fn find_self_closing_end(buffer: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    let mut quote_char = '"';
+
+    for (i, ch) in buffer.char_indices() {
+        match ch {
+            '"' | '\'' if !in_quotes => {
+                in_quotes = true;
+                quote_char = ch;
+            }
+            c if c == quote_char && in_quotes => {
+                in_quotes = false;
+            }
+            '/' if !in_quotes && buffer[i + 1..].starts_with('>') => {
+                return Some(i + 2);
+            }
+            '>' if !in_quotes => {
+                // A bare '>' before any '/>' means the opening tag closed and
+                // element has body content — not a self-closing tag.
+                return None;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_element_attributes() {
+        let raw = r#"<presence id='5' to="duo@br1.pvp.net"><show>chat</show></presence>"#;
+        let stanza = Stanza::parse(raw).unwrap();
+        assert!(stanza.is("presence"));
+        assert_eq!(stanza.attr("id"), Some("5"));
+        assert_eq!(stanza.attr("to"), Some("duo@br1.pvp.net"));
+        assert_eq!(stanza.attr("type"), None);
+        assert_eq!(stanza.body, "<show>chat</show>");
+        assert_eq!(stanza.raw, raw);
+    }
+
+    #[test]
+    fn test_parse_self_closing() {
+        let stanza = Stanza::parse(r#"<presence type="unavailable"/>"#).unwrap();
+        assert!(stanza.self_closing);
+        assert_eq!(stanza.attr("type"), Some("unavailable"));
+        assert_eq!(stanza.body, "");
+    }
+
+    #[test]
+    fn test_parse_gt_inside_attribute() {
+        let stanza = Stanza::parse(r#"<message id="a>b"><body>x</body></message>"#).unwrap();
+        assert_eq!(stanza.attr("id"), Some("a>b"));
+        assert_eq!(stanza.body, "<body>x</body>");
+    }
+
+    #[test]
+    fn test_parse_stream_units() {
+        let open = Stanza::parse(r#"<stream:stream xmlns="jabber:client" to="server">"#).unwrap();
+        assert_eq!(open.kind, StanzaKind::StreamOpen);
+        assert_eq!(open.attr("to"), Some("server"));
+        assert_eq!(
+            Stanza::parse("</stream:stream>").unwrap().kind,
+            StanzaKind::Close
+        );
+        assert_eq!(
+            Stanza::parse("<?xml version='1.0'?>").unwrap().kind,
+            StanzaKind::Declaration
+        );
+        assert_eq!(Stanza::parse(" ").unwrap().kind, StanzaKind::Text);
+    }
+
+    #[test]
+    fn test_parse_rejects_incomplete() {
+        assert_eq!(Stanza::parse("<presence><show>chat</show>"), None);
+        assert_eq!(Stanza::parse(r#"<presence to="x"#), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_quote_in_attribute_name() {
+        assert_eq!(Stanza::parse("<presence xml'lang='en'/>"), None);
+        assert_eq!(Stanza::parse(r#"<presence a"b="c"/>"#), None);
+    }
+
+    #[test]
+    fn test_children() {
+        let raw =
+            "<presence><show>chat</show><games><keystone><pty/></keystone></games></presence>";
+        let stanza = Stanza::parse(raw).unwrap();
+        let names: Vec<&str> = stanza.children().iter().map(|c| c.name).collect();
+        assert_eq!(names, vec!["show", "games"]);
+        assert_eq!(stanza.children()[1].children()[0].name, "keystone");
+    }
+
+    #[test]
+    fn test_retain_children() {
+        let raw = r#"<message to='duo@br1.pvp.net'><x/> <body>gg</body> <x/></message>"#;
+        let stanza = Stanza::parse(raw).unwrap();
+        assert_eq!(
+            stanza.retain_children(|c| c.name != "x"),
+            "<message to='duo@br1.pvp.net'> <body>gg</body></message>"
+        );
+        assert_eq!(stanza.retain_children(|_| true), raw);
+    }
+
+    #[test]
+    fn test_with_first_child() {
+        let raw = r#"<presence id='5'><status>hi</status><show>chat</show></presence>"#;
+        let stanza = Stanza::parse(raw).unwrap();
+        assert_eq!(
+            stanza.with_first_child("<show>away</show>", |c| !c.is("show")),
+            "<presence id='5'><show>away</show><status>hi</status></presence>"
+        );
+        let empty = Stanza::parse("<presence to='a@b'/>").unwrap();
+        assert_eq!(
+            empty.with_first_child("<show>dnd</show>", |_| true),
+            "<presence to='a@b'><show>dnd</show></presence>"
+        );
+    }
+
+    #[test]
+    fn test_render_and_leading_attribute() {
+        let raw = r#"<presence id='5' type="available"><show/></presence>"#;
+        let stanza = Stanza::parse(raw).unwrap();
+        let kept = stanza.attributes.iter().copied().filter(|a| a.name != "type");
+        assert_eq!(stanza.render_empty(kept), "<presence id='5'/>");
+
+        let to = Attribute {
+            name: "to",
+            value: "duo@br1.pvp.net",
+            quote: '"',
+        };
+        assert_eq!(
+            stanza.with_leading_attribute(to),
+            r#"<presence to="duo@br1.pvp.net" id='5' type="available"><show/></presence>"#
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+
+/// Files in the client's config dir holding persisted logins ("stay signed
+/// in"). Newer clients keep one per product.
+const PRIVATE_SETTINGS_FILES: &[&str] = &[
+    "RiotGamesPrivateSettings.yaml",
+    "RiotClientPrivateSettings.yaml",
+];
+
+/// A Riot account the client has a persisted login for. The files don't hold
+/// the Riot ID, only the PUUID, which is also the user part of the account's
+/// chat JID (`<puuid>@<server>.pvp.net`).
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RiotAccount {
+    pub puuid: String,
+    pub region: Option<String>,
+}
+
+/// Accounts with a persisted login in the Riot Client's private settings.
+/// Missing, unreadable or encrypted files are skipped.
+pub fn read_accounts() -> Vec<RiotAccount> {
+    let Some(dir) = super::client_config_dir() else {
+        return Vec::new();
+    };
+
+    let mut seen = HashSet::new();
+    let mut accounts = Vec::new();
+    for name in PRIVATE_SETTINGS_FILES {
+        let path = dir.join(name);
+        let Ok(bytes) = std::fs::read(&path) else {
+            continue;
+        };
+        let Ok(content) = String::from_utf8(bytes) else {
+            log::warn!("{} is not plain text (encrypted?) — skipped", path.display());
+            continue;
+        };
+        let found = parse_accounts(&content);
+        if found.is_empty() && !content.contains("riot-login") {
+            log::warn!("{} has no readable login data — skipped", path.display());
+        }
+        accounts.extend(found.into_iter().filter(|a| seen.insert(a.puuid.clone())));
+    }
+    accounts
+}
+
+/// Extract accounts from the private settings YAML: the PUUID is the value
+/// of the `sub` auth cookie, the region is `riot-login.persist.region`.
+/// Only these keys are needed, so no YAML parser.
+pub fn parse_accounts(content: &str) -> Vec<RiotAccount> {
+    let region = super::client_settings::parse_region(content);
+    let mut accounts: Vec<RiotAccount> = Vec::new();
+    let mut name = None;
+    let mut value = None;
+
+    // Each cookie is a list item; its keys come in any order
+    let mut flush = |name: &mut Option<String>, value: &mut Option<String>| {
+        if let (Some("sub"), Some(puuid)) = (name.take().as_deref(), value.take()) {
+            if !puuid.is_empty() && !accounts.iter().any(|a| a.puuid == puuid) {
+                accounts.push(RiotAccount {
+                    puuid,
+                    region: region.clone(),
+                });
+            }
+        }
+    };
+
+    for line in content.lines() {
+        let mut line = line.trim();
+        if let Some(item) = line.strip_prefix("- ") {
+            flush(&mut name, &mut value);
+            line = item.trim_start();
+        }
+        let Some((key, val)) = line.split_once(':') else {
+            continue;
+        };
+        let val = val.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+        match key.trim() {
+            "name" => name = Some(val),
+            "value" => value = Some(val),
+            _ => {}
+        }
+    }
+    flush(&mut name, &mut value);
+    accounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = r#"riot-login:
+    persist:
+        region: "BR"
+        session:
+            cookies:
+              - domain: "auth.riotgames.com"
+                hostOnly: true
+                name: "tdid"
+                value: "device-id"
+              - domain: "auth.riotgames.com"
+                hostOnly: true
+                name: "sub"
+                path: "/"
+                value: "0b1c2d3e-puuid"
+              - value: "ignored"
+                name: "clid"
+"#;
+
+    #[test]
+    fn test_parse_accounts() {
+        assert_eq!(
+            parse_accounts(SETTINGS),
+            vec![RiotAccount {
+                puuid: "0b1c2d3e-puuid".to_string(),
+                region: Some("br".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_accounts_value_before_name() {
+        let content = "cookies:\n  - value: \"abc\"\n    name: \"sub\"\n";
+        assert_eq!(parse_accounts(content)[0].puuid, "abc");
+    }
+
+    #[test]
+    fn test_parse_accounts_none() {
+        assert!(parse_accounts("riot-login:\n    persist: null\n").is_empty());
+        assert!(parse_accounts("").is_empty());
+    }
+}
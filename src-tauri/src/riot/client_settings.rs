@@ -0,0 +1,43 @@
+/// Region the Riot Client was last used with, from `RiotClientSettings.yaml`
+/// in the client's config dir. None if the client was never run here.
+pub fn read_region() -> Option<String> {
+    let path = super::client_config_dir()?.join("RiotClientSettings.yaml");
+    let content = std::fs::read_to_string(&path).ok()?;
+    let region = parse_region(&content);
+    if region.is_none() {
+        log::warn!("No region found in {}", path.display());
+    }
+    region
+}
+
+/// Extract the `region` value (`install.globals.region`) from the settings
+/// YAML. Only that one key is needed, so no YAML parser.
+pub fn parse_region(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("region:")?;
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        (!value.is_empty()).then(|| value.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_region() {
+        let content = "install:\n    globals:\n        locale: \"pt_BR\"\n        region: \"BR\"\n";
+        assert_eq!(parse_region(content).as_deref(), Some("br"));
+    }
+
+    #[test]
+    fn test_parse_region_unquoted() {
+        assert_eq!(parse_region("region: EUW\n").as_deref(), Some("euw"));
+    }
+
+    #[test]
+    fn test_parse_region_missing() {
+        assert_eq!(parse_region("install:\n    globals:\n        locale: \"en_US\"\n"), None);
+        assert_eq!(parse_region("region: \"\"\n"), None);
+    }
+}
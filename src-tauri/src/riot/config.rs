@@ -1,45 +1,123 @@
-/// Known Riot chat server addresses by region.
-/// Fallback for when we can't extract it from the config proxy.
-pub fn chat_server_for_region(region: &str) -> Option<&'static str> {
-    match region.to_lowercase().as_str() {
-        "br" | "br1" => Some("br1.chat.si.riotgames.com"),
-        "eun" | "eun1" => Some("eun1.chat.si.riotgames.com"),
-        "euw" | "euw1" => Some("euw1.chat.si.riotgames.com"),
-        "jp" | "jp1" => Some("jp1.chat.si.riotgames.com"),
-        "kr" | "kr1" => Some("kr1.chat.si.riotgames.com"),
-        "la1" | "lan" => Some("la1.chat.si.riotgames.com"),
-        "la2" | "las" => Some("la2.chat.si.riotgames.com"),
-        "na" | "na1" | "na2" => Some("na2.chat.si.riotgames.com"),
-        "oc" | "oc1" | "oce" => Some("oc1.chat.si.riotgames.com"),
-        "ph" | "ph2" => Some("ph2.chat.si.riotgames.com"),
-        "ru" | "ru1" => Some("ru1.chat.si.riotgames.com"),
-        "sg" | "sg2" => Some("sg2.chat.si.riotgames.com"),
-        "th" | "th2" => Some("th2.chat.si.riotgames.com"),
-        "tr" | "tr1" => Some("tr1.chat.si.riotgames.com"),
-        "tw" | "tw2" => Some("tw2.chat.si.riotgames.com"),
-        "vn" | "vn2" => Some("vn2.chat.si.riotgames.com"),
-        _ => None,
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Built-in region table. A `regions.json` in the data dir with the same
+/// shape overrides entries by code (or adds new ones), so a changed host can
+/// be patched without a new release.
+const BUILTIN_REGIONS: &str = include_str!("regions.json");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionEntry {
+    pub code: String,
+    pub name: String,
+    pub host: String,
+    /// Other codes that resolve to this region (e.g. `lan` for `la1`).
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Region → chat host table used for the region selector and as a fallback
+/// when the chat host can't be extracted by the config proxy.
+#[derive(Debug, Clone)]
+pub struct RegionTable {
+    entries: Vec<RegionEntry>,
+}
+
+impl Default for RegionTable {
+    fn default() -> Self {
+        let entries =
+            serde_json::from_str(BUILTIN_REGIONS).expect("built-in regions.json is valid");
+        Self { entries }
+    }
+}
+
+impl RegionTable {
+    pub fn entries(&self) -> &[RegionEntry] {
+        &self.entries
+    }
+
+    /// Known Riot chat server address for a region code. Shard numbers change
+    /// over time, so any number is accepted as long as the base code is
+    /// unambiguous: `sg`, `sg2` and `sg3` all resolve to the Singapore host.
+    pub fn chat_server_for_region(&self, region: &str) -> Option<&str> {
+        let region = region.trim().to_lowercase();
+
+        let exact = self
+            .entries
+            .iter()
+            .find(|e| e.code == region || e.aliases.iter().any(|a| *a == region));
+        if let Some(entry) = exact {
+            return Some(&entry.host);
+        }
+
+        let base = base_code(&region);
+        if base.is_empty() {
+            return None;
+        }
+        let mut matches = self.entries.iter().filter(|e| base_code(&e.code) == base);
+        match (matches.next(), matches.next()) {
+            (Some(entry), None) => Some(&entry.host),
+            _ => None,
+        }
+    }
+
+    /// Ordered chat host candidates: the selected region's host first, then
+    /// the common fallbacks, without duplicates.
+    pub fn fallback_chat_hosts(&self, region: Option<&str>) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        let regional = region.and_then(|r| self.chat_server_for_region(r));
+        for host in regional.into_iter().chain(COMMON_FALLBACK_HOSTS.iter().copied()) {
+            if !hosts.iter().any(|h| h == host) {
+                hosts.push(host.to_string());
+            }
+        }
+        hosts
+    }
+
+    /// Replace or add entries by code.
+    fn merge(&mut self, overrides: Vec<RegionEntry>) {
+        for entry in overrides {
+            let code = entry.code.to_lowercase();
+            match self.entries.iter_mut().find(|e| e.code == code) {
+                Some(existing) => *existing = RegionEntry { code, ..entry },
+                None => self.entries.push(RegionEntry { code, ..entry }),
+            }
+        }
     }
 }
 
-/// List of all known regions for a dropdown selector.
-pub const REGIONS: &[(&str, &str)] = &[
-    ("br", "Brazil"),
-    ("eun", "EU Nordic & East"),
-    ("euw", "EU West"),
-    ("jp", "Japan"),
-    ("kr", "Korea"),
-    ("la1", "Latin America North"),
-    ("la2", "Latin America South"),
-    ("na", "North America"),
-    ("oc", "Oceania"),
-    ("ph", "Philippines"),
-    ("ru", "Russia"),
-    ("sg", "Singapore"),
-    ("th", "Thailand"),
-    ("tr", "Turkey"),
-    ("tw", "Taiwan"),
-    ("vn", "Vietnam"),
+fn base_code(code: &str) -> &str {
+    code.trim_end_matches(|c: char| c.is_ascii_digit())
+}
+
+fn regions_override_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("regions.json")
+}
+
+/// Built-in table with the data dir's `regions.json` applied on top, if any.
+/// An invalid override is ignored so a typo can't break region selection.
+pub fn load_regions(app_data_dir: &Path) -> RegionTable {
+    let mut table = RegionTable::default();
+    let path = regions_override_path(app_data_dir);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return table;
+    };
+
+    match serde_json::from_str::<Vec<RegionEntry>>(&content) {
+        Ok(overrides) => {
+            log::info!("Applying {} region override(s) from {:?}", overrides.len(), path);
+            table.merge(overrides);
+        }
+        Err(e) => log::warn!("Invalid regions file {:?}: {e} — using built-in regions", path),
+    }
+    table
+}
+
+/// Hosts tried, in order, when the preferred one doesn't accept connections.
+const COMMON_FALLBACK_HOSTS: &[&str] = &[
+    "na2.chat.si.riotgames.com",
+    "euw1.chat.si.riotgames.com",
 ];
 
 #[cfg(test)]
@@ -49,21 +127,115 @@ mod tests {
     #[test]
     fn test_known_regions() {
         assert_eq!(
-            chat_server_for_region("br"),
+            RegionTable::default().chat_server_for_region("br"),
             Some("br1.chat.si.riotgames.com")
         );
         assert_eq!(
-            chat_server_for_region("na"),
+            RegionTable::default().chat_server_for_region("na"),
             Some("na2.chat.si.riotgames.com")
         );
         assert_eq!(
-            chat_server_for_region("euw1"),
+            RegionTable::default().chat_server_for_region("euw1"),
             Some("euw1.chat.si.riotgames.com")
         );
     }
 
     #[test]
     fn test_unknown_region() {
-        assert_eq!(chat_server_for_region("unknown"), None);
+        assert_eq!(RegionTable::default().chat_server_for_region("unknown"), None);
+        assert_eq!(RegionTable::default().chat_server_for_region("42"), None);
+    }
+
+    #[test]
+    fn test_any_shard_number_matches() {
+        for code in ["sg", "sg2", "sg3", "SG3"] {
+            assert_eq!(
+                RegionTable::default().chat_server_for_region(code),
+                Some("sg2.chat.si.riotgames.com"),
+                "{code}"
+            );
+        }
+        assert_eq!(
+            RegionTable::default().chat_server_for_region("th3"),
+            Some("th2.chat.si.riotgames.com")
+        );
+        assert_eq!(
+            RegionTable::default().chat_server_for_region("na1"),
+            Some("na2.chat.si.riotgames.com")
+        );
+    }
+
+    #[test]
+    fn test_latam_shards_stay_distinct() {
+        assert_eq!(
+            RegionTable::default().chat_server_for_region("la1"),
+            Some("la1.chat.si.riotgames.com")
+        );
+        assert_eq!(
+            RegionTable::default().chat_server_for_region("la2"),
+            Some("la2.chat.si.riotgames.com")
+        );
+        assert_eq!(
+            RegionTable::default().chat_server_for_region("oce"),
+            Some("oc1.chat.si.riotgames.com")
+        );
+    }
+
+    #[test]
+    fn test_fallback_hosts_start_with_region() {
+        assert_eq!(
+            RegionTable::default().fallback_chat_hosts(Some("br")),
+            vec![
+                "br1.chat.si.riotgames.com",
+                "na2.chat.si.riotgames.com",
+                "euw1.chat.si.riotgames.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fallback_hosts_deduplicated() {
+        assert_eq!(
+            RegionTable::default().fallback_chat_hosts(Some("na")),
+            vec!["na2.chat.si.riotgames.com", "euw1.chat.si.riotgames.com"]
+        );
+        assert_eq!(RegionTable::default().fallback_chat_hosts(None).len(), 2);
+    }
+
+    #[test]
+    fn test_override_replaces_host() {
+        let mut table = RegionTable::default();
+        table.merge(vec![RegionEntry {
+            code: "BR".to_string(),
+            name: "Brazil".to_string(),
+            host: "br2.chat.si.riotgames.com".to_string(),
+            aliases: Vec::new(),
+        }]);
+        assert_eq!(
+            table.chat_server_for_region("br1"),
+            Some("br2.chat.si.riotgames.com")
+        );
+        assert_eq!(table.entries().len(), RegionTable::default().entries().len());
+    }
+
+    #[test]
+    fn test_override_adds_region() {
+        let mut table = RegionTable::default();
+        table.merge(vec![RegionEntry {
+            code: "me1".to_string(),
+            name: "Middle East".to_string(),
+            host: "me1.chat.si.riotgames.com".to_string(),
+            aliases: Vec::new(),
+        }]);
+        assert_eq!(
+            table.chat_server_for_region("me"),
+            Some("me1.chat.si.riotgames.com")
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_base_code() {
+        // la1 and la2 share the "la" base, so an unknown shard can't pick one
+        assert_eq!(RegionTable::default().chat_server_for_region("la3"), None);
     }
 }
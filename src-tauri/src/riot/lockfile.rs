@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+/// Contents of the Riot Client lockfile, written while the client is running.
+/// Format: `name:pid:port:password:protocol`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Lockfile {
+    pub name: String,
+    pub pid: u32,
+    pub port: u16,
+    pub password: String,
+    pub protocol: String,
+}
+
+/// Parse the lockfile contents. Returns None if the format is unexpected.
+pub fn parse(content: &str) -> Option<Lockfile> {
+    let mut parts = content.trim().split(':');
+    let name = parts.next()?.to_string();
+    let pid = parts.next()?.parse().ok()?;
+    let port = parts.next()?.parse().ok()?;
+    let password = parts.next()?.to_string();
+    let protocol = parts.next()?.to_string();
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(Lockfile {
+        name,
+        pid,
+        port,
+        password,
+        protocol,
+    })
+}
+
+/// Read and parse the Riot Client lockfile, if the client is running.
+pub fn read_lockfile() -> Option<Lockfile> {
+    let path = lockfile_path()?;
+    let content = std::fs::read_to_string(&path).ok()?;
+    let lockfile = parse(&content);
+    if lockfile.is_none() {
+        log::warn!("Unrecognized lockfile format at {}", path.display());
+    }
+    lockfile
+}
+
+fn lockfile_path() -> Option<PathBuf> {
+    super::client_config_dir().map(|d| d.join("lockfile"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lockfile() {
+        let lockfile = parse("Riot Client:12345:54321:s3cr3t-Pw:https\n").unwrap();
+        assert_eq!(lockfile.name, "Riot Client");
+        assert_eq!(lockfile.pid, 12345);
+        assert_eq!(lockfile.port, 54321);
+        assert_eq!(lockfile.password, "s3cr3t-Pw");
+        assert_eq!(lockfile.protocol, "https");
+    }
+
+    #[test]
+    fn test_parse_lockfile_invalid() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("Riot Client:abc:54321:pw:https"), None);
+        assert_eq!(parse("Riot Client:1:2:pw"), None);
+    }
+}
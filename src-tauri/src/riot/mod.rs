@@ -1,2 +1,34 @@
+pub mod accounts;
+pub mod client_settings;
 pub mod config;
+pub mod lockfile;
 pub mod process;
+
+use std::path::PathBuf;
+
+/// The Riot Client's per-user config directory (lockfile, client settings).
+pub fn client_config_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    {
+        // %LOCALAPPDATA%\Riot Games\Riot Client\Config
+        std::env::var("LOCALAPPDATA").ok().map(|d| {
+            PathBuf::from(d)
+                .join("Riot Games")
+                .join("Riot Client")
+                .join("Config")
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // ~/Library/Application Support/Riot Games/Riot Client/Config
+        std::env::var("HOME").ok().map(|h| {
+            PathBuf::from(h).join("Library/Application Support/Riot Games/Riot Client/Config")
+        })
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        None
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::path::PathBuf;
 use sysinfo::System;
 
@@ -42,7 +43,9 @@ pub fn kill_riot_processes() -> Result<(), String> {
     Ok(())
 }
 
-/// Find the Riot Client executable path.
+/// Find the Riot Client executable path. Linux uses `find_linux_client`,
+/// which also knows how to start it.
+#[cfg(not(target_os = "linux"))]
 pub fn find_riot_client() -> Option<PathBuf> {
     #[cfg(target_os = "macos")]
     {
@@ -60,6 +63,104 @@ pub fn find_riot_client() -> Option<PathBuf> {
     }
 }
 
+/// Names a native Linux Riot Client could ship under. None exists yet; these
+/// are looked up on `PATH` so a future build is picked up without changes.
+#[cfg(any(target_os = "linux", test))]
+const NATIVE_CLIENT_NAMES: &[&str] = &["RiotClientServices", "riot-client"];
+
+/// The Windows client inside a Wine prefix, relative to the prefix.
+#[cfg(any(target_os = "linux", test))]
+const WINE_CLIENT_PATH: &str = "drive_c/Riot Games/Riot Client/RiotClientServices.exe";
+
+/// How the Riot Client is started on Linux.
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinuxClient {
+    Native(PathBuf),
+    Wine { prefix: PathBuf, exe: PathBuf },
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl LinuxClient {
+    pub fn path(&self) -> &Path {
+        match self {
+            LinuxClient::Native(path) => path,
+            LinuxClient::Wine { exe, .. } => exe,
+        }
+    }
+
+    /// The command that starts the client with `args`, which are passed
+    /// through unchanged either way.
+    pub fn command(&self, args: &[String]) -> std::process::Command {
+        match self {
+            LinuxClient::Native(path) => {
+                let mut command = std::process::Command::new(path);
+                command.args(args);
+                command
+            }
+            LinuxClient::Wine { prefix, exe } => {
+                let mut command = std::process::Command::new("wine");
+                command.env("WINEPREFIX", prefix).arg(exe).args(args);
+                command
+            }
+        }
+    }
+}
+
+/// A native client on `path_dirs` wins; otherwise the first Wine prefix with
+/// the Windows client installed.
+#[cfg(any(target_os = "linux", test))]
+fn select_linux_client(
+    path_dirs: &[PathBuf],
+    prefixes: &[PathBuf],
+    exists: impl Fn(&Path) -> bool,
+) -> Option<LinuxClient> {
+    let native = path_dirs
+        .iter()
+        .flat_map(|dir| NATIVE_CLIENT_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|path| exists(path));
+    if let Some(path) = native {
+        return Some(LinuxClient::Native(path));
+    }
+
+    prefixes.iter().find_map(|prefix| {
+        let exe = prefix.join(WINE_CLIENT_PATH);
+        exists(&exe).then(|| LinuxClient::Wine {
+            prefix: prefix.clone(),
+            exe,
+        })
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn find_linux_client() -> Option<LinuxClient> {
+    let path_dirs: Vec<PathBuf> = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).collect())
+        .unwrap_or_default();
+
+    // $WINEPREFIX first, then Lutris' default prefix and Wine's own default
+    let mut prefixes: Vec<PathBuf> = std::env::var_os("WINEPREFIX")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    if let Some(home) = dirs::home_dir() {
+        prefixes.push(home.join("Games/league-of-legends"));
+        prefixes.push(home.join(".wine"));
+    }
+
+    let client = select_linux_client(&path_dirs, &prefixes, |p| p.is_file());
+    match &client {
+        Some(LinuxClient::Native(path)) => {
+            log::info!("Found native Riot Client at: {}", path.display())
+        }
+        Some(LinuxClient::Wine { prefix, .. }) => {
+            log::info!("Found Riot Client in Wine prefix: {}", prefix.display())
+        }
+        None => log::debug!("No native Riot Client or Wine prefix with one found"),
+    }
+    client
+}
+
 #[cfg(target_os = "macos")]
 fn find_riot_client_macos() -> Option<PathBuf> {
     let mut candidates: Vec<PathBuf> = vec![
@@ -152,17 +253,118 @@ fn get_installs_json_path() -> Option<PathBuf> {
     }
 }
 
-/// Launch the Riot Client with a specific game and config proxy URL.
+/// Prefix of the error returned when the requested game isn't installed, so
+/// the UI can say so instead of leaving the Riot Client to fail on its own.
+pub const GAME_NOT_INSTALLED_ERROR: &str = "game_not_installed";
+
+/// Install folder name Riot uses for each product, as it appears in the
+/// `associated_client` paths of `RiotClientInstalls.json`.
+fn product_folder(game: &str) -> Option<&'static str> {
+    match game {
+        "league_of_legends" => Some("League of Legends"),
+        "valorant" => Some("VALORANT"),
+        _ => None,
+    }
+}
+
+/// Whether `game` is installed, from the per-product install manifest under
+/// Riot's metadata dir or the `associated_client` entries of
+/// `RiotClientInstalls.json`. None when neither source is available, so
+/// callers don't block a launch they can't judge.
+fn product_installed(
+    game: &str,
+    metadata_dir: Option<&Path>,
+    installs: Option<&serde_json::Value>,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<bool> {
+    let folder = product_folder(game)?;
+
+    let metadata_dir = metadata_dir.filter(|dir| exists(dir));
+    if let Some(dir) = metadata_dir {
+        let manifest = dir
+            .join(format!("{game}.live"))
+            .join(format!("{game}.live.product_settings.yaml"));
+        if exists(&manifest) {
+            return Some(true);
+        }
+    }
+
+    let associated = installs
+        .and_then(|json| json.get("associated_client"))
+        .and_then(|v| v.as_object());
+    if let Some(associated) = associated {
+        let listed = associated.keys().any(|install_dir| {
+            install_dir
+                .trim_end_matches(['/', '\\'])
+                .rsplit(['/', '\\'])
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case(folder))
+        });
+        if listed {
+            return Some(true);
+        }
+    }
+
+    (metadata_dir.is_some() || associated.is_some()).then_some(false)
+}
+
+/// Riot's per-product install metadata (`<product>.live/*.product_settings.yaml`).
+fn get_metadata_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    {
+        // %ProgramData%\Riot Games\Metadata
+        std::env::var("ProgramData")
+            .ok()
+            .map(|pd| PathBuf::from(pd).join("Riot Games").join("Metadata"))
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        Some(PathBuf::from("/Users/Shared/Riot Games/Metadata"))
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        None
+    }
+}
+
+/// Fail with `GAME_NOT_INSTALLED_ERROR` when `game` is known not to be
+/// installed. Passes when installs can't be determined (e.g. on Linux).
+pub fn ensure_game_installed(game: &str) -> Result<(), String> {
+    let installs: Option<serde_json::Value> = get_installs_json_path()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok());
+    let installed = product_installed(
+        game,
+        get_metadata_dir().as_deref(),
+        installs.as_ref(),
+        |p| p.exists(),
+    );
+    if installed == Some(false) {
+        log::warn!("Launch blocked: '{game}' is not installed");
+        return Err(format!("{GAME_NOT_INSTALLED_ERROR}: {game} is not installed"));
+    }
+    Ok(())
+}
+
+/// Launch the Riot Client with a specific game. With a config proxy port the
+/// client fetches its config through us; without one it talks to Riot directly.
 pub fn launch_riot_client(
     game: &str,
-    config_proxy_port: u16,
+    config_proxy_port: Option<u16>,
 ) -> Result<(), String> {
-    let client_path = find_riot_client().ok_or_else(|| {
+    let not_found = || {
         log::error!("Riot Client not found at any known path");
         "Riot Client not found. Is it installed?".to_string()
-    })?;
-
-    let config_url = format!("http://127.0.0.1:{config_proxy_port}");
+    };
+    // On Linux the search already knows whether to go through Wine
+    #[cfg(target_os = "linux")]
+    let client = find_linux_client().ok_or_else(not_found)?;
+    #[cfg(target_os = "linux")]
+    let client_path = client.path();
+    #[cfg(not(target_os = "linux"))]
+    let client_path = find_riot_client().ok_or_else(not_found)?;
 
     let launch_product = match game {
         "league_of_legends" => "--launch-product=league_of_legends",
@@ -170,22 +372,20 @@ pub fn launch_riot_client(
         _ => return Err(format!("Unknown game: {game}")),
     };
 
-    log::info!(
-        "Launching Riot Client: {:?} --client-config-url=\"{config_url}\" {launch_product}",
-        client_path
-    );
+    let mut args = Vec::new();
+    if let Some(port) = config_proxy_port {
+        args.push(format!("--client-config-url=http://127.0.0.1:{port}"));
+    }
+    args.push(launch_product.to_string());
+    args.push("--launch-patchline=live".to_string());
+
+    log::info!("Launching Riot Client: {:?} {}", client_path, args.join(" "));
 
     #[cfg(target_os = "macos")]
     {
         std::process::Command::new("open")
-            .args([
-                "-a",
-                client_path.to_str().unwrap_or_default(),
-                "--args",
-                &format!("--client-config-url={config_url}"),
-                launch_product,
-                "--launch-patchline=live",
-            ])
+            .args(["-a", client_path.to_str().unwrap_or_default(), "--args"])
+            .args(&args)
             .spawn()
             .map_err(|e| format!("Failed to launch Riot Client: {e}"))?;
     }
@@ -193,11 +393,15 @@ pub fn launch_riot_client(
     #[cfg(target_os = "windows")]
     {
         std::process::Command::new(&client_path)
-            .args([
-                &format!("--client-config-url={config_url}"),
-                launch_product,
-                "--launch-patchline=live",
-            ])
+            .args(&args)
+            .spawn()
+            .map_err(|e| format!("Failed to launch Riot Client: {e}"))?;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        client
+            .command(&args)
             .spawn()
             .map_err(|e| format!("Failed to launch Riot Client: {e}"))?;
     }
@@ -240,3 +444,103 @@ mod dirs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> Vec<String> {
+        vec![
+            "--client-config-url=http://127.0.0.1:4000".to_string(),
+            "--launch-product=league_of_legends".to_string(),
+            "--launch-patchline=live".to_string(),
+        ]
+    }
+
+    fn args_of(command: &std::process::Command) -> Vec<String> {
+        command.get_args().map(|a| a.to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn test_installed_from_product_manifest() {
+        let metadata = Path::new("/ProgramData/Riot Games/Metadata");
+        let manifest = metadata.join("valorant.live/valorant.live.product_settings.yaml");
+        let exists = |p: &Path| p == metadata || p == manifest;
+        assert_eq!(product_installed("valorant", Some(metadata), None, exists), Some(true));
+        assert_eq!(
+            product_installed("league_of_legends", Some(metadata), None, exists),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_installed_from_associated_client() {
+        let installs = serde_json::json!({
+            "associated_client": {
+                "C:/Riot Games/League of Legends/": "C:/Riot Games/Riot Client/RiotClient.exe"
+            },
+            "rc_default": "C:/Riot Games/Riot Client/RiotClientServices.exe"
+        });
+        let none = |_: &Path| false;
+        assert_eq!(
+            product_installed("league_of_legends", None, Some(&installs), none),
+            Some(true)
+        );
+        assert_eq!(product_installed("valorant", None, Some(&installs), none), Some(false));
+    }
+
+    #[test]
+    fn test_install_state_unknown_without_sources() {
+        let installs = serde_json::json!({ "rc_default": "/x" });
+        let none = |_: &Path| false;
+        assert_eq!(product_installed("valorant", None, None, none), None);
+        assert_eq!(product_installed("valorant", Some(Path::new("/m")), None, none), None);
+        assert_eq!(product_installed("valorant", None, Some(&installs), none), None);
+        assert_eq!(product_installed("tft", None, None, |_| true), None);
+    }
+
+    #[test]
+    fn test_native_client_preferred_over_wine() {
+        let dirs = [PathBuf::from("/usr/local/bin"), PathBuf::from("/usr/bin")];
+        let prefixes = [PathBuf::from("/home/u/.wine")];
+        let client = select_linux_client(&dirs, &prefixes, |_| true);
+        assert_eq!(
+            client,
+            Some(LinuxClient::Native(PathBuf::from("/usr/local/bin/RiotClientServices")))
+        );
+    }
+
+    #[test]
+    fn test_wine_prefix_fallback() {
+        let dirs = [PathBuf::from("/usr/bin")];
+        let prefixes = [PathBuf::from("/home/u/Games/lol"), PathBuf::from("/home/u/.wine")];
+        let client = select_linux_client(&dirs, &prefixes, |p| p.starts_with("/home/u/.wine"));
+        assert_eq!(
+            client,
+            Some(LinuxClient::Wine {
+                prefix: PathBuf::from("/home/u/.wine"),
+                exe: PathBuf::from("/home/u/.wine").join(WINE_CLIENT_PATH),
+            })
+        );
+        assert_eq!(select_linux_client(&dirs, &prefixes, |_| false), None);
+    }
+
+    #[test]
+    fn test_launch_args_identical_native_and_wine() {
+        let native = LinuxClient::Native(PathBuf::from("/usr/bin/riot-client")).command(&args());
+        assert_eq!(native.get_program(), "/usr/bin/riot-client");
+        assert_eq!(args_of(&native), args());
+
+        let wine = LinuxClient::Wine {
+            prefix: PathBuf::from("/p"),
+            exe: PathBuf::from("/p/RiotClientServices.exe"),
+        }
+        .command(&args());
+        assert_eq!(wine.get_program(), "wine");
+        let wine_args = args_of(&wine);
+        assert_eq!(wine_args[0], "/p/RiotClientServices.exe");
+        assert_eq!(wine_args[1..], args());
+        let env: Vec<_> = wine.get_envs().collect();
+        assert_eq!(env, [(std::ffi::OsStr::new("WINEPREFIX"), Some(std::ffi::OsStr::new("/p")))]);
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::proxy::config_proxy;
+use crate::proxy::injection::InjectionJitter;
+use crate::proxy::presence::OfflinePresence;
+
+/// User preferences persisted as `settings.json` in the app data dir.
+/// Missing fields fall back to their defaults, so older files keep loading.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Hide the window on close instead of quitting.
+    pub close_to_tray: bool,
+    /// Random delay before injecting presence on a mode toggle.
+    pub injection_jitter: InjectionJitter,
+    /// Ordered chat hosts to try when the detected one is unreachable.
+    /// Empty derives the list from the selected region.
+    pub chat_host_candidates: Vec<String>,
+    /// Seconds to wait for Riot's config server before failing the request.
+    pub config_timeout_secs: u64,
+    /// Whether Offline mode rewrites or drops broadcast/directed presence.
+    pub offline_presence: OfflinePresence,
+    /// Keep the last presence on disk so it can be restored after a restart.
+    /// Off by default since it stores presence data at rest.
+    pub persist_presence: bool,
+    /// Warn (after one re-injection) when the server doesn't echo injected
+    /// unavailable presence. Opt-in: not every server echoes it.
+    pub verify_unavailable_ack: bool,
+    /// While invisible, also strip typing notifications and receipts from
+    /// outgoing messages.
+    pub strict_offline: bool,
+    /// Fixed port for the config proxy, for firewall rules and external
+    /// tools. None picks a free port on every launch.
+    pub config_proxy_port: Option<u16>,
+    /// Send a whitespace keepalive upstream after this many idle seconds.
+    /// None leaves keepalives to the client.
+    pub upstream_keepalive_secs: Option<u64>,
+    /// True until onboarding (certs generated and the CA trusted) has been
+    /// completed once; the window opens at startup while it is. A missing
+    /// settings file counts as a first run.
+    pub first_run: bool,
+    /// Open the window at startup even after the first run, instead of
+    /// starting in the tray.
+    pub show_window_on_start: bool,
+    /// Config server to proxy to instead of Riot's, in case Riot moves it.
+    /// `$WIT_CONFIG_URL` takes precedence. None uses Riot's.
+    pub upstream_config_url: Option<String>,
+    /// Re-check every few minutes that the CA is still trusted, warning if
+    /// it was removed.
+    pub ca_trust_check: bool,
+    /// Allow chat hosts outside Riot's domains. Off by default so a tampered
+    /// config or setting can't aim the proxy at the local network.
+    pub allow_arbitrary_chat_host: bool,
+    /// Install the CA during first-run setup when it isn't trusted, which
+    /// shows the OS admin prompt. Off so setup never prompts unexpectedly.
+    pub auto_install_ca: bool,
+    /// Request headers the config proxy forwards to Riot, overriding the
+    /// built-in list. None uses the built-in list.
+    pub config_headers: Option<Vec<String>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            close_to_tray: true,
+            injection_jitter: InjectionJitter::default(),
+            chat_host_candidates: Vec::new(),
+            config_timeout_secs: config_proxy::DEFAULT_UPSTREAM_TIMEOUT.as_secs(),
+            offline_presence: OfflinePresence::default(),
+            persist_presence: false,
+            verify_unavailable_ack: false,
+            strict_offline: false,
+            config_proxy_port: None,
+            upstream_keepalive_secs: None,
+            first_run: true,
+            show_window_on_start: false,
+            upstream_config_url: None,
+            ca_trust_check: true,
+            allow_arbitrary_chat_host: false,
+            auto_install_ca: false,
+            config_headers: None,
+        }
+    }
+}
+
+fn settings_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("settings.json")
+}
+
+/// Load settings from disk, falling back to defaults if the file is absent
+/// or unreadable.
+pub fn load(app_data_dir: &Path) -> Settings {
+    let path = settings_path(app_data_dir);
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => {
+            log::info!("No settings file at {:?} — using defaults", path);
+            return Settings::default();
+        }
+    };
+
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        log::warn!("Invalid settings file {:?}: {e} — using defaults", path);
+        Settings::default()
+    })
+}
+
+/// Write settings to disk.
+pub fn save(app_data_dir: &Path, settings: &Settings) -> Result<(), String> {
+    fs::create_dir_all(app_data_dir).map_err(|e| format!("Failed to create data dir: {e}"))?;
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize settings: {e}"))?;
+    fs::write(settings_path(app_data_dir), json)
+        .map_err(|e| format!("Failed to write settings: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_file_is_first_run() {
+        let dir = std::env::temp_dir().join(format!("wit-settings-{}-none", std::process::id()));
+        assert!(load(&dir).first_run);
+    }
+
+    #[test]
+    fn test_first_run_flag_round_trip() {
+        let dir = std::env::temp_dir().join(format!("wit-settings-{}-saved", std::process::id()));
+        let settings = Settings {
+            first_run: false,
+            ..Settings::default()
+        };
+        save(&dir, &settings).unwrap();
+        assert!(!load(&dir).first_run);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,11 +1,46 @@
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use tokio::sync::watch;
 
+use crate::proxy::capture::TrafficCapture;
+use crate::proxy::config_proxy::{self, ChatHostRefresher, ConfigDump, ConfigStats};
+use crate::proxy::injection::InjectionJitter;
+use crate::proxy::presence::VisibilityRules;
+use crate::proxy::session::{AccountModes, ConnectionCounter, PresenceCache};
+use crate::proxy::xmpp_proxy::UpstreamRoots;
+use crate::riot::config::RegionTable;
+use crate::settings::Settings;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum StealthMode {
     Online,
     Offline,
+    /// Visible to friends, but shown as away (`<show>away</show>`).
+    Away,
+    /// Visible to friends, but shown as busy (`<show>dnd</show>`).
+    Dnd,
+    /// Visible to friends, but shown as away for a while (`<show>xa</show>`).
+    ExtendedAway,
+}
+
+impl StealthMode {
+    /// Whether friends see the account as offline.
+    pub fn is_hidden(&self) -> bool {
+        *self == StealthMode::Offline
+    }
+
+    /// `<show>` value forced on outgoing presence. None for Online, which
+    /// keeps the client's own, and for Offline, which sends no presence.
+    pub fn show(&self) -> Option<&'static str> {
+        match self {
+            StealthMode::Online | StealthMode::Offline => None,
+            StealthMode::Away => Some("away"),
+            StealthMode::Dnd => Some("dnd"),
+            StealthMode::ExtendedAway => Some("xa"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -20,6 +55,21 @@ pub struct StatusInfo {
     pub stealth_mode: StealthMode,
     pub proxy_status: ProxyStatus,
     pub connected_game: Option<String>,
+    pub active_connections: usize,
+    /// Requests the config proxy has served this launch.
+    pub config_requests_received: u64,
+    /// Config responses this launch with chat config redirected to us, and
+    /// those forwarded unchanged.
+    pub config_responses: ConfigStats,
+    /// Whether Riot's config for this launch had chat settings; None until
+    /// config has come through. False means chat is off for the account.
+    pub chat_config_present: Option<bool>,
+    /// Address the XMPP proxy is listening on, while it is.
+    pub listen_addr: Option<String>,
+    /// Chat severed with `disconnect_chat` while the proxy keeps running.
+    pub chat_disconnected: bool,
+    /// Unix time (seconds) a `pause_stealth` ends, while one is running.
+    pub stealth_paused_until: Option<u64>,
 }
 
 pub struct AppState {
@@ -28,13 +78,72 @@ pub struct AppState {
 
 pub struct AppStateInner {
     pub stealth_mode: StealthMode,
+    /// Last mode friends could see the account in, which `--toggle` returns
+    /// to from Offline. Kept in step by `set_stealth_mode`.
+    pub last_visible_mode: StealthMode,
     pub proxy_status: ProxyStatus,
     pub connected_game: Option<String>,
     pub detected_region: Option<String>,
     pub detected_chat_host: Option<String>,
+    pub upstream_proxy: Option<String>,
+    pub settings: Settings,
+    /// Region → chat host table, built-in plus the `regions.json` override.
+    pub regions: RegionTable,
+    /// Last presence per account for the current proxy session.
+    pub presence_cache: PresenceCache,
+    /// Live client connections through the XMPP proxy.
+    pub connections: ConnectionCounter,
+    /// Decrypted traffic dump, off unless enabled with `set_capture`.
+    pub capture: TrafficCapture,
+    /// Per-contact visibility rules; lives for the whole app so running
+    /// proxies pick up changes immediately.
+    pub visibility_tx: watch::Sender<VisibilityRules>,
+    pub jitter_tx: watch::Sender<InjectionJitter>,
+    /// Headers the config proxy forwards to Riot, live on a running proxy.
+    pub config_headers_tx: watch::Sender<Vec<String>>,
+    /// Per-account stealth overrides (bare JID → mode) for running several
+    /// Riot accounts at once. Accounts not listed follow `stealth_mode`.
+    pub account_modes_tx: watch::Sender<AccountModes>,
     pub mode_tx: Option<watch::Sender<StealthMode>>,
     pub shutdown_tx: Option<watch::Sender<bool>>,
+    pub proxy_ready_rx: Option<watch::Receiver<Option<SocketAddr>>>,
+    /// Trust roots the XMPP proxy's upstream connector loaded at its last
+    /// start. Kept after it stops, for diagnosing failed launches.
+    pub upstream_roots: Option<UpstreamRoots>,
+    /// Re-injects the current presence on the running proxy (`reapply_presence`).
+    pub reapply_tx: Option<watch::Sender<()>>,
+    /// Severs chat on the running XMPP proxy (`disconnect_chat`).
+    pub chat_disconnected_tx: Option<watch::Sender<bool>>,
     pub config_shutdown_tx: Option<watch::Sender<bool>>,
+    /// Port the config proxy is listening on, while it runs.
+    pub config_port: Option<u16>,
+    pub config_requests_rx: Option<watch::Receiver<u64>>,
+    pub config_stats_rx: Option<watch::Receiver<ConfigStats>>,
+    /// Last chat config the config proxy patched. Kept after it stops, for
+    /// diagnosing patching that silently did nothing.
+    pub last_config_rx: Option<watch::Receiver<Option<ConfigDump>>>,
+    /// Live XMPP proxy target and a way to re-fetch it from Riot, while a
+    /// game launch is running.
+    pub host_tx: Option<Arc<watch::Sender<String>>>,
+    pub chat_host_refresher: Option<ChatHostRefresher>,
+    /// Local mock chat server, only while test mode is running.
+    pub mock_shutdown_tx: Option<watch::Sender<bool>>,
+    /// Whether the tray icon exists. Without it a hidden window can't be
+    /// brought back, so the window is never hidden.
+    pub tray_available: bool,
+    /// Running `pause_stealth`, cleared by any other mode change.
+    pub stealth_pause: Option<StealthPause>,
+    /// Id of the latest pause, so a superseded pause's timer does nothing.
+    pub pause_generation: u64,
+}
+
+/// A `pause_stealth` in progress: Online until `until`, then back to
+/// `resume_mode`.
+#[derive(Debug, Clone)]
+pub struct StealthPause {
+    pub id: u64,
+    pub resume_mode: StealthMode,
+    pub until: SystemTime,
 }
 
 impl Default for AppState {
@@ -42,14 +151,137 @@ impl Default for AppState {
         Self {
             inner: Mutex::new(AppStateInner {
                 stealth_mode: StealthMode::Offline,
+                last_visible_mode: StealthMode::Online,
                 proxy_status: ProxyStatus::Idle,
                 connected_game: None,
                 detected_region: None,
                 detected_chat_host: None,
+                upstream_proxy: None,
+                settings: Settings::default(),
+                regions: RegionTable::default(),
+                presence_cache: PresenceCache::default(),
+                connections: ConnectionCounter::default(),
+                capture: TrafficCapture::default(),
+                visibility_tx: watch::channel(VisibilityRules::default()).0,
+                jitter_tx: watch::channel(InjectionJitter::default()).0,
+                config_headers_tx: watch::channel(config_proxy::default_forwarded_headers()).0,
+                account_modes_tx: watch::channel(AccountModes::new()).0,
                 mode_tx: None,
                 shutdown_tx: None,
+                proxy_ready_rx: None,
+                upstream_roots: None,
+                chat_disconnected_tx: None,
+                reapply_tx: None,
                 config_shutdown_tx: None,
+                config_port: None,
+                config_requests_rx: None,
+                config_stats_rx: None,
+                last_config_rx: None,
+                host_tx: None,
+                chat_host_refresher: None,
+                mock_shutdown_tx: None,
+                tray_available: false,
+                stealth_pause: None,
+                pause_generation: 0,
+            }),
+        }
+    }
+}
+
+impl AppStateInner {
+    /// Record `mode` as the current mode, remembering it as the one to toggle
+    /// back to if friends can see it. Doesn't reach a running proxy.
+    pub fn set_stealth_mode(&mut self, mode: StealthMode) {
+        if !mode.is_hidden() {
+            self.last_visible_mode = mode.clone();
+        }
+        self.stealth_mode = mode;
+    }
+
+    pub fn status(&self) -> StatusInfo {
+        let config_responses = self
+            .config_stats_rx
+            .as_ref()
+            .map(|rx| *rx.borrow())
+            .unwrap_or_default();
+        StatusInfo {
+            stealth_mode: self.stealth_mode.clone(),
+            proxy_status: self.proxy_status.clone(),
+            connected_game: self.connected_game.clone(),
+            active_connections: self.connections.get(),
+            config_requests_received: self
+                .config_requests_rx
+                .as_ref()
+                .map_or(0, |rx| *rx.borrow()),
+            config_responses,
+            chat_config_present: config_responses.chat_config_present(),
+            listen_addr: self
+                .proxy_ready_rx
+                .as_ref()
+                .and_then(|rx| *rx.borrow())
+                .map(|addr| addr.to_string()),
+            chat_disconnected: self
+                .chat_disconnected_tx
+                .as_ref()
+                .is_some_and(|tx| *tx.borrow()),
+            stealth_paused_until: self.stealth_pause.as_ref().and_then(|pause| {
+                let until = pause.until.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+                Some(until.as_secs())
             }),
         }
     }
 }
+
+/// How long the config proxy waits for the XMPP proxy to release its
+/// listener before shutting down anyway.
+const CONFIG_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stop both proxies and reset the runtime fields. Every exit path (stop,
+/// reset, quit, window close) goes through here so they can't drift apart.
+pub fn teardown_proxy(inner: &mut AppStateInner) {
+    // Order matters: XMPP proxy first, config proxy last. With the config
+    // proxy gone while the client is still fetching config, the fetch fails
+    // and the client can fall back to Riot's real chat server, announcing the
+    // account online before it notices we're gone.
+    let xmpp_running = inner.shutdown_tx.is_some();
+    if let Some(tx) = inner.shutdown_tx.take() {
+        let _ = tx.send(true);
+    }
+    if let Some(tx) = inner.mock_shutdown_tx.take() {
+        let _ = tx.send(true);
+    }
+    if let Some(tx) = inner.config_shutdown_tx.take() {
+        match inner.proxy_ready_rx.clone().filter(|_| xmpp_running) {
+            // Callers hold the state lock, often on the main thread, so the
+            // wait for the XMPP listener to go away happens off it.
+            Some(mut ready_rx) => {
+                tauri::async_runtime::spawn(async move {
+                    let stopped = tokio::time::timeout(
+                        CONFIG_SHUTDOWN_TIMEOUT,
+                        ready_rx.wait_for(Option::is_none),
+                    )
+                    .await;
+                    if !matches!(stopped, Ok(Ok(_))) {
+                        log::warn!("XMPP proxy still bound — stopping the config proxy anyway");
+                    }
+                    let _ = tx.send(true);
+                });
+            }
+            None => {
+                let _ = tx.send(true);
+            }
+        }
+    }
+    inner.mode_tx = None;
+    inner.proxy_ready_rx = None;
+    inner.chat_disconnected_tx = None;
+    inner.reapply_tx = None;
+    inner.config_port = None;
+    inner.config_requests_rx = None;
+    inner.config_stats_rx = None;
+    inner.host_tx = None;
+    inner.chat_host_refresher = None;
+    inner.proxy_status = ProxyStatus::Idle;
+    inner.connected_game = None;
+    inner.presence_cache.clear();
+}
@@ -1,13 +1,44 @@
 import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import "./App.css";
-import { CertStatus, RegionInfo, StatusInfo } from "./types";
+import {
+  CertStatus,
+  ChatFailed,
+  RegionInfo,
+  StatusInfo,
+  StealthMode,
+} from "./types";
+
+const MODE_LABELS: Record<StealthMode, string> = {
+  Online: "Online",
+  Offline: "Invisivel",
+  Away: "Ausente",
+  Dnd: "Ocupado",
+  ExtendedAway: "Ausente (longo)",
+};
+
+// Value `set_stealth_mode` takes for each mode
+const MODE_VALUES: Record<StealthMode, string> = {
+  Online: "online",
+  Offline: "offline",
+  Away: "away",
+  Dnd: "dnd",
+  ExtendedAway: "xa",
+};
 
 function App() {
   const [status, setStatus] = useState<StatusInfo>({
     stealth_mode: "Offline",
     proxy_status: "Idle",
     connected_game: null,
+    active_connections: 0,
+    config_requests_received: 0,
+    config_responses: { patched: 0, passed_through: 0 },
+    chat_config_present: null,
+    listen_addr: null,
+    chat_disconnected: false,
+    stealth_paused_until: null,
   });
   const [certStatus, setCertStatus] = useState<CertStatus | null>(null);
   const [regions, setRegions] = useState<RegionInfo[]>([]);
@@ -15,6 +46,9 @@ function App() {
   const [installing, setInstalling] = useState(false);
   const [launching, setLaunching] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  const [untrustedLaunch, setUntrustedLaunch] = useState<string | null>(null);
+  const [tlsInterference, setTlsInterference] = useState<string | null>(null);
+  const [chatUnavailable, setChatUnavailable] = useState(false);
 
   useEffect(() => {
     invoke<StatusInfo>("get_status").then(setStatus);
@@ -22,22 +56,101 @@ function App() {
     invoke<RegionInfo[]>("get_regions").then(setRegions);
   }, []);
 
+  useEffect(() => {
+    const refresh = () => invoke<StatusInfo>("get_status").then(setStatus);
+    const unlisteners = [
+      listen("client-connected", refresh),
+      listen("client-disconnected", refresh),
+      listen("config-proxy-first-request", refresh),
+      listen("chat-connected", refresh),
+      listen<StatusInfo>("stealth-pause-ended", (event) =>
+        setStatus(event.payload),
+      ),
+      listen<StatusInfo>("status-changed", (event) => setStatus(event.payload)),
+      listen("ca-trust-lost", () => {
+        invoke<CertStatus>("get_cert_status").then(setCertStatus);
+        setError(
+          "O certificado deixou de ser confiavel (foi removido do sistema). " +
+            "Instale-o de novo para o modo invisivel funcionar.",
+        );
+      }),
+      listen<number>("clock-skew-detected", (event) =>
+        setError(
+          `O relogio do sistema esta ${Math.round(Math.abs(event.payload) / 60)} min ` +
+            `${event.payload > 0 ? "adiantado" : "atrasado"}. Ajuste a data/hora ` +
+            "para o chat funcionar.",
+        ),
+      ),
+      listen<ChatFailed>("chat-failed", (event) => {
+        // Not a proxy problem: Riot has chat off for this account/region
+        if (event.payload.cause === "chat_unavailable") {
+          setChatUnavailable(true);
+        } else {
+          setError(event.payload.message);
+        }
+      }),
+      listen<string>("tls-interference-detected", (event) =>
+        setTlsInterference(event.payload),
+      ),
+    ];
+    return () => {
+      unlisteners.forEach((p) => p.then((unlisten) => unlisten()));
+    };
+  }, []);
+
+  async function setVisibleMode(mode: string) {
+    try {
+      const updated = await invoke<StatusInfo>("set_stealth_mode", { mode });
+      setStatus(updated);
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
   async function toggleStealth() {
     const newMode = status.stealth_mode === "Offline" ? "online" : "offline";
-    const updated = await invoke<StatusInfo>("set_stealth_mode", {
-      mode: newMode,
-    });
-    setStatus(updated);
+    try {
+      const updated = await invoke<StatusInfo>("set_stealth_mode", {
+        mode: newMode,
+      });
+      setStatus(updated);
+    } catch (e) {
+      setError(String(e));
+    }
   }
 
-  async function handleLaunch(game: string) {
+  async function pauseStealth() {
+    try {
+      const updated = await invoke<StatusInfo>("pause_stealth", {
+        seconds: 5 * 60,
+      });
+      setStatus(updated);
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
+  async function handleLaunch(game: string, ignoreUntrustedCa = false) {
     setLaunching(true);
     setError(null);
+    setUntrustedLaunch(null);
+    setChatUnavailable(false);
     try {
-      const updated = await invoke<StatusInfo>("launch_game", { game });
+      const updated = await invoke<StatusInfo>("launch_game", {
+        game,
+        ignoreUntrustedCa,
+      });
       setStatus(updated);
     } catch (e) {
-      setError(String(e));
+      if (String(e).startsWith("ca_not_trusted")) {
+        setUntrustedLaunch(game);
+      } else if (String(e).startsWith("game_not_installed")) {
+        setError(
+          "Esse jogo nao esta instalado. Instale-o pelo Riot Client primeiro.",
+        );
+      } else {
+        setError(String(e));
+      }
     } finally {
       setLaunching(false);
     }
@@ -49,6 +162,25 @@ function App() {
     setError(null);
   }
 
+  async function toggleChatConnection() {
+    try {
+      const updated = await invoke<StatusInfo>(
+        status.chat_disconnected ? "reconnect_chat" : "disconnect_chat",
+      );
+      setStatus(updated);
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
+  async function handleReapply() {
+    try {
+      await invoke("reapply_presence");
+    } catch (e) {
+      setError(String(e));
+    }
+  }
+
   async function handleInstallCa() {
     setInstalling(true);
     try {
@@ -101,6 +233,48 @@ function App() {
         </div>
       )}
 
+      {untrustedLaunch && (
+        <div className="banner banner-warn">
+          <p>
+            Certificado nao confiavel. Sem ele o chat provavelmente nao vai
+            funcionar.
+          </p>
+          <button
+            className="btn btn-outline-warn"
+            onClick={() => handleLaunch(untrustedLaunch, true)}
+            disabled={launching}
+          >
+            Abrir mesmo assim
+          </button>
+        </div>
+      )}
+
+      {tlsInterference && (
+        <div className="banner banner-warn">
+          <p>
+            Outro programa esta interceptando HTTPS (provavelmente o antivirus:
+            {" "}
+            {tlsInterference}). Desative a verificacao HTTPS dele para o chat
+            funcionar.
+          </p>
+          <button className="dismiss" onClick={() => setTlsInterference(null)}>
+            x
+          </button>
+        </div>
+      )}
+
+      {chatUnavailable && (
+        <div className="banner banner-warn">
+          <p>
+            Chat indisponivel para esta conta/regiao: a Riot nao enviou
+            configuracao de chat.
+          </p>
+          <button className="dismiss" onClick={() => setChatUnavailable(false)}>
+            x
+          </button>
+        </div>
+      )}
+
       {error && (
         <div className="banner banner-error">
           <p>{error}</p>
@@ -118,7 +292,7 @@ function App() {
           <span className="toggle-dot" />
           <div className="toggle-text">
             <span className="toggle-label">
-              {isOffline ? "Invisivel" : "Online"}
+              {isOffline ? "Invisivel" : MODE_LABELS[status.stealth_mode]}
             </span>
             <span className="toggle-hint">
               {isOffline
@@ -127,6 +301,31 @@ function App() {
             </span>
           </div>
         </button>
+        {!isOffline && (
+          <select
+            className="select"
+            value={MODE_VALUES[status.stealth_mode]}
+            onChange={(e) => setVisibleMode(e.target.value)}
+          >
+            <option value="online">Online</option>
+            <option value="away">Ausente</option>
+            <option value="dnd">Ocupado</option>
+            <option value="xa">Ausente (longo)</option>
+          </select>
+        )}
+        {status.stealth_paused_until ? (
+          <p className="launch-hint">
+            Online ate{" "}
+            {new Date(status.stealth_paused_until * 1000).toLocaleTimeString()}
+            , depois volta a ficar invisivel.
+          </p>
+        ) : (
+          isOffline && (
+            <button className="btn btn-outline-warn" onClick={pauseStealth}>
+              Ficar online por 5 min
+            </button>
+          )
+        )}
       </div>
 
       <div className="card">
@@ -176,8 +375,17 @@ function App() {
               Proxy ativo
               {status.connected_game &&
                 ` — ${status.connected_game.replace("_", " ")}`}
+              {status.chat_disconnected
+                ? " (chat cortado)"
+                : status.active_connections === 0 && " (aguardando chat)"}
             </span>
           </div>
+          <button className="btn-outline-warn" onClick={handleReapply}>
+            Reaplicar
+          </button>
+          <button className="btn-outline-warn" onClick={toggleChatConnection}>
+            {status.chat_disconnected ? "Reconectar chat" : "Cortar chat"}
+          </button>
           <button className="btn btn-stop" onClick={handleStop}>
             Parar
           </button>
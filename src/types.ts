@@ -1,14 +1,31 @@
-export type StealthMode = "Online" | "Offline";
+export type StealthMode = "Online" | "Offline" | "Away" | "Dnd" | "ExtendedAway";
 
 export type ProxyStatus =
   | "Idle"
   | "Running"
   | { Error: string };
 
+export type ConfigStats = {
+  patched: number;
+  passed_through: number;
+};
+
 export type StatusInfo = {
   stealth_mode: StealthMode;
   proxy_status: ProxyStatus;
   connected_game: string | null;
+  active_connections: number;
+  config_requests_received: number;
+  config_responses: ConfigStats;
+  chat_config_present: boolean | null;
+  listen_addr: string | null;
+  chat_disconnected: boolean;
+  stealth_paused_until: number | null;
+};
+
+export type ChatFailed = {
+  cause: "bypassed" | "untrusted_ca" | "unreachable" | "chat_unavailable";
+  message: string;
 };
 
 export type CertStatus = {
@@ -21,3 +38,8 @@ export type RegionInfo = {
   code: string;
   name: string;
 };
+
+export type ToggleSimulation = {
+  inject: string | null;
+  restores_last_presence: boolean;
+};